@@ -0,0 +1,25 @@
+use clipboard2json::{ClipboardData, DedupMode, FileSink, Sink};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// Appends a copy to histories of increasing size. The time per append stays
+/// the same since the file is never read or rewritten after the first write.
+fn append(c: &mut Criterion) {
+    let mut group = c.benchmark_group("append");
+    for &size in &[100, 1_000, 10_000] {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = FileSink::with_dedup(dir.path().join("clipboard.json"), DedupMode::Merge);
+        for i in 0..size {
+            sink.write(&ClipboardData::new((format!("copy {}", i), None)))
+                .unwrap();
+        }
+
+        let data = ClipboardData::new(("https://example.com/".to_string(), None));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            b.iter(|| sink.write(data).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, append);
+criterion_main!(benches);
@@ -0,0 +1,210 @@
+use crate::common::ClipboardFunctions;
+use crate::headless_clipboard::HeadlessClipboard;
+use failure::{bail, format_err, Error};
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+
+/// Identifies a clipboard backend that can be selected at runtime.
+///
+/// `Auto` picks the first backend in the registry that is usable in the current
+/// session, which is detected through the `WAYLAND_DISPLAY` and `DISPLAY`
+/// environment variables on Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Auto,
+    X11,
+    Wayland,
+    WinApi,
+    Headless,
+}
+
+impl BackendKind {
+    /// Names accepted by `FromStr`, used in the CLI help and error messages.
+    pub const NAMES: &'static [&'static str] = &["auto", "x11", "wayland", "winapi", "headless"];
+}
+
+impl FromStr for BackendKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(BackendKind::Auto),
+            "x11" => Ok(BackendKind::X11),
+            "wayland" => Ok(BackendKind::Wayland),
+            "winapi" | "windows" => Ok(BackendKind::WinApi),
+            "headless" => Ok(BackendKind::Headless),
+            _ => bail!(
+                "Unknown backend '{}', expected one of: {}",
+                s,
+                BackendKind::NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BackendKind::Auto => "auto",
+            BackendKind::X11 => "x11",
+            BackendKind::Wayland => "wayland",
+            BackendKind::WinApi => "winapi",
+            BackendKind::Headless => "headless",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An entry in the backend registry.
+///
+/// `detect` performs a cheap check of whether the backend can work in the
+/// current session and explains why not when it cannot, while `open` actually
+/// connects to the clipboard.
+pub struct Backend {
+    pub kind: BackendKind,
+    /// Whether the backend takes part in automatic detection
+    pub auto: bool,
+    detect: fn() -> Result<(), Error>,
+    open: fn() -> Result<Box<dyn ClipboardFunctions>, Error>,
+}
+
+impl Backend {
+    /// Checks whether the backend is usable in the current session.
+    pub fn detect(&self) -> Result<(), Error> {
+        (self.detect)()
+    }
+
+    /// Connects to the clipboard through this backend.
+    pub fn open(&self) -> Result<Box<dyn ClipboardFunctions>, Error> {
+        (self.open)()
+    }
+}
+
+/// Fails with a descriptive error when the environment variable is not set.
+#[allow(dead_code)]
+fn require_env(name: &str) -> Result<(), Error> {
+    match env::var_os(name) {
+        Some(ref value) if !value.is_empty() => Ok(()),
+        _ => bail!("{} is not set", name),
+    }
+}
+
+#[cfg(target_os = "linux")]
+const PLATFORM_BACKENDS: &[Backend] = &[
+    Backend {
+        kind: BackendKind::Wayland,
+        auto: true,
+        detect: || {
+            require_env("WAYLAND_DISPLAY")?;
+            crate::clipboard::wayland_clipboard::ClipboardOwner::probe()
+        },
+        open: || {
+            Ok(Box::new(
+                crate::clipboard::wayland_clipboard::ClipboardOwner::new()?,
+            ))
+        },
+    },
+    Backend {
+        kind: BackendKind::X11,
+        auto: true,
+        detect: || require_env("DISPLAY"),
+        open: || {
+            Ok(Box::new(
+                crate::clipboard::x11_clipboard::ClipboardOwner::new()?,
+            ))
+        },
+    },
+];
+
+#[cfg(windows)]
+const PLATFORM_BACKENDS: &[Backend] = &[Backend {
+    kind: BackendKind::WinApi,
+    auto: true,
+    detect: || Ok(()),
+    open: || {
+        Ok(Box::new(
+            crate::clipboard::winapi_clipboard::ClipboardOwner::new()?,
+        ))
+    },
+}];
+
+#[cfg(not(any(target_os = "linux", windows)))]
+const PLATFORM_BACKENDS: &[Backend] = &[];
+
+const HEADLESS_BACKEND: Backend = Backend {
+    kind: BackendKind::Headless,
+    auto: false,
+    detect: || Ok(()),
+    open: || Ok(Box::new(HeadlessClipboard::new())),
+};
+
+/// Returns every backend compiled into this build in the order in which
+/// automatic detection tries them.
+pub fn backends() -> Vec<&'static Backend> {
+    PLATFORM_BACKENDS
+        .iter()
+        .chain(std::iter::once(&HEADLESS_BACKEND))
+        .collect()
+}
+
+/// Entry point for connecting to the system clipboard.
+pub struct Clipboard;
+
+impl Clipboard {
+    /// Opens the clipboard with the requested backend.
+    ///
+    /// With `BackendKind::Auto` each registered backend that takes part in
+    /// detection is tried in order and the first one that can be opened is
+    /// returned. If none of them work, the error lists every backend that was
+    /// tried along with the reason it was rejected.
+    pub fn open(kind: BackendKind) -> Result<Box<dyn ClipboardFunctions>, Error> {
+        if kind != BackendKind::Auto {
+            let backend = backends()
+                .into_iter()
+                .find(|backend| backend.kind == kind)
+                .ok_or_else(|| {
+                    format_err!("The {} backend is not available on this platform", kind)
+                })?;
+            return backend.open();
+        }
+
+        let mut tried = Vec::new();
+        for backend in backends().into_iter().filter(|backend| backend.auto) {
+            match backend.detect().and_then(|_| backend.open()) {
+                Ok(clipboard) => return Ok(clipboard),
+                Err(e) => tried.push(format!("{}: {}", backend.kind, e)),
+            }
+        }
+
+        if tried.is_empty() {
+            bail!("No clipboard backend is available on this platform");
+        }
+        bail!(
+            "Could not open a clipboard backend (tried {})",
+            tried.join("; ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_backend_kind() {
+        assert_eq!("X11".parse::<BackendKind>().unwrap(), BackendKind::X11);
+        assert_eq!("auto".parse::<BackendKind>().unwrap(), BackendKind::Auto);
+        let err = "cocoa".parse::<BackendKind>().unwrap_err().to_string();
+        assert!(err.contains("wayland"));
+    }
+
+    #[test]
+    fn test_headless_is_not_auto_detected() {
+        let headless = backends()
+            .into_iter()
+            .find(|backend| backend.kind == BackendKind::Headless)
+            .unwrap();
+        assert!(!headless.auto);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::common::{hash_content, ClipboardData};
+use crate::segments::sync_dir;
+use crate::store::{create_private_dir, private_options};
+use failure::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Reference to a payload that is stored in the blob store instead of the
+/// record, whose `content` is then left empty.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct BlobRef {
+    /// Hex encoded SHA-256 hash of the payload, which is also its name
+    pub sha256: String,
+    /// Size of the payload in bytes
+    pub size: u64,
+    pub mime: String,
+}
+
+/// Content-addressed store for the large payloads of a history, in the
+/// `<path>.blobs` directory next to it.
+///
+/// Every payload is written once under its SHA-256 hash, in a subdirectory
+/// named after the first two characters of the hash, no matter how many
+/// records refer to it.
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    /// Blob store of the history at `path`. Nothing is created until the
+    /// first payload is stored.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let mut dir = path.as_ref().as_os_str().to_owned();
+        dir.push(".blobs");
+        BlobStore {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    /// Moves the content of the record to the store when it is larger than
+    /// `threshold` bytes. Returns whether it was moved.
+    ///
+    /// The fields derived from the content, such as the text of HTML, the
+    /// parsed document or the table, are cleared as well since they are as
+    /// large, and are rebuilt by `inline`. The content as it was copied is
+    /// dropped when it is larger than `threshold` too.
+    pub fn offload(&self, data: &mut ClipboardData, threshold: u64) -> Result<bool, Error> {
+        let size = data.content().len() as u64;
+        if size <= threshold || data.meta().blob.is_some() {
+            return Ok(false);
+        }
+        let mime = match data {
+            ClipboardData::Html { .. } => "text/html; charset=utf-8",
+            ClipboardData::UnicodeText { .. } => "text/plain; charset=utf-8",
+        };
+        let blob = BlobRef {
+            sha256: hash_content(data.content()),
+            size,
+            mime: mime.to_string(),
+        };
+        self.put(&blob.sha256, data.content().as_bytes())?;
+        data.content_mut().clear();
+        if let ClipboardData::Html {
+            text,
+            markdown,
+            links,
+            images,
+            ..
+        } = data
+        {
+            text.clear();
+            // Left empty rather than unset so that `inline` renders it again
+            if let Some(markdown) = markdown {
+                markdown.clear();
+            }
+            links.clear();
+            images.clear();
+        }
+        let meta = data.meta_mut();
+        meta.table = None;
+        meta.parsed = None;
+        meta.stack_trace = None;
+        meta.log.clear();
+        meta.url_parts = None;
+        meta.clean_url = None;
+        if meta
+            .raw
+            .as_ref()
+            .is_some_and(|raw| raw.len() as u64 > threshold)
+        {
+            meta.raw = None;
+        }
+        meta.blob = Some(blob);
+        Ok(true)
+    }
+
+    /// Puts the content of the record back from the store and rebuilds the
+    /// fields derived from it.
+    pub fn inline(&self, data: &mut ClipboardData) -> Result<(), Error> {
+        let blob = match data.meta_mut().blob.take() {
+            Some(blob) => blob,
+            None => return Ok(()),
+        };
+        let payload = self.read(&blob)?;
+        *data.content_mut() = String::from_utf8(payload)
+            .map_err(|_| format_err!("The blob {} is not valid UTF-8", blob.sha256))?;
+        data.refresh();
+        Ok(())
+    }
+
+    /// Reads a payload, checking that it still matches its hash.
+    pub fn read(&self, blob: &BlobRef) -> Result<Vec<u8>, Error> {
+        let path = self.path(&blob.sha256)?;
+        let payload = fs::read(&path)
+            .map_err(|e| format_err!("Could not read the blob {}: {}", path.display(), e))?;
+        let sha256 = Sha256::digest(&payload)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        if payload.len() as u64 != blob.size || sha256 != blob.sha256 {
+            bail!("The blob {} was modified", path.display());
+        }
+        Ok(payload)
+    }
+
+    /// Removes the payloads that none of the records refer to. Returns the
+    /// number of payloads removed.
+    pub fn collect_garbage<'a, I>(&self, records: I) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = &'a ClipboardData>,
+    {
+        let referenced = records
+            .into_iter()
+            .filter_map(|data| data.meta().blob.as_ref())
+            .map(|blob| blob.sha256.as_str())
+            .collect::<HashSet<_>>();
+        let prefixes = match fs::read_dir(&self.dir) {
+            Ok(prefixes) => prefixes,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let mut removed = 0;
+        for prefix in prefixes {
+            let prefix = prefix?.path();
+            if !prefix.is_dir() {
+                continue;
+            }
+            for blob in fs::read_dir(&prefix)? {
+                let blob = blob?.path();
+                let name = blob.file_name().and_then(|name| name.to_str());
+                // Temporary files of interrupted writes are removed as well
+                if name.is_some_and(|name| !referenced.contains(name)) {
+                    fs::remove_file(&blob)?;
+                    removed += 1;
+                }
+            }
+            if fs::read_dir(&prefix)?.next().is_none() {
+                fs::remove_dir(&prefix)?;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn put(&self, sha256: &str, payload: &[u8]) -> Result<(), Error> {
+        let path = self.path(sha256)?;
+        if path.exists() {
+            return Ok(());
+        }
+        create_private_dir(path.parent().unwrap_or(&self.dir))?;
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut file = private_options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp)?;
+        file.write_all(payload)?;
+        file.sync_all()?;
+        fs::rename(&temp, &path)?;
+        sync_dir(&path)
+    }
+
+    fn path(&self, sha256: &str) -> Result<PathBuf, Error> {
+        if sha256.len() != 64 || !sha256.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            bail!("Invalid blob hash '{}'", sha256);
+        }
+        Ok(self.dir.join(&sha256[..2]).join(sha256))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::Sink;
+    use crate::retention::Retention;
+    use crate::sinks::FileSink;
+    use crate::store::{prune, read_history};
+
+    #[test]
+    fn test_offload_and_collect() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        let large = "x".repeat(100);
+        let mut sink = FileSink::new(&path).with_blob_threshold(64);
+        for content in &[large.as_str(), large.as_str(), "small"] {
+            sink.write(&ClipboardData::new((content.to_string(), None)))
+                .unwrap();
+        }
+        let store = BlobStore::new(&path);
+        let mut history = read_history(&path).unwrap();
+        assert_eq!(history[0].content(), "");
+        let blob = history[0].meta().blob.clone().unwrap();
+        assert_eq!(
+            (blob.size, blob.mime.as_str()),
+            (100, "text/plain; charset=utf-8")
+        );
+        assert_eq!(fs::read_dir(&store.dir).unwrap().count(), 1);
+        store.inline(&mut history[0]).unwrap();
+        assert_eq!(history[0].content(), large);
+        assert!(history[1].meta().blob.is_none());
+
+        let retention = Retention {
+            max_entries: Some(1),
+            ..Retention::default()
+        };
+        prune(&path, &retention, None).unwrap();
+        assert_eq!(fs::read_dir(&store.dir).unwrap().count(), 0);
+        assert!(store.read(&blob).is_err());
+
+        // The fields derived from the content stay out of the record too
+        let path = dir.path().join("derived.json");
+        let json = format!("[{}]", vec![r#"{"key": "value"}"#; 200].join(", "));
+        let html = format!(
+            "<table>{}</table>",
+            r#"<tr><td><a href="https://example.com/">cell</a></td><td>2</td></tr>"#.repeat(200)
+        );
+        let originals = vec![
+            ClipboardData::new((json, None)),
+            ClipboardData::new((html, None, None)),
+        ];
+        assert!(originals[0].meta().parsed.is_some());
+        assert!(originals[1].meta().table.is_some());
+        let mut sink = FileSink::new(&path).with_blob_threshold(64);
+        for data in &originals {
+            sink.write(data).unwrap();
+        }
+        for line in fs::read_to_string(&path).unwrap().lines() {
+            assert!(line.len() < 1024, "{}", line);
+        }
+        let store = BlobStore::new(&path);
+        let mut history = read_history(&path).unwrap();
+        for (data, original) in history.iter_mut().zip(&originals) {
+            store.inline(data).unwrap();
+            assert_eq!(data, original);
+        }
+    }
+}
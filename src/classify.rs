@@ -0,0 +1,395 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// What the copied content looks like, so that the history can be sliced
+/// without parsing the content again.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Url,
+    Email,
+    /// Unix or Windows file system path
+    Path,
+    Phone,
+    /// Hex, `rgb()`, `rgba()`, `hsl()` or `hsla()` colour
+    Color,
+    Number,
+    /// Date or date and time in one of the common formats
+    Date,
+    Json,
+    /// Source code, the guessed language is stored alongside
+    Code,
+    /// Shell command line, possibly prefixed with a `$` prompt
+    Command,
+    /// Anything else, usually plain text
+    Prose,
+}
+
+/// Size of the content.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub chars: usize,
+    pub words: usize,
+    pub lines: usize,
+}
+
+impl Stats {
+    pub fn of(text: &str) -> Self {
+        Stats {
+            chars: text.chars().count(),
+            words: text.split_whitespace().count(),
+            lines: text.lines().count(),
+        }
+    }
+}
+
+/// Programs that make a line look like a shell command.
+const COMMANDS: &[&str] = &[
+    "apt",
+    "apt-get",
+    "awk",
+    "brew",
+    "bash",
+    "cargo",
+    "cat",
+    "cd",
+    "chmod",
+    "chown",
+    "cp",
+    "curl",
+    "docker",
+    "echo",
+    "export",
+    "find",
+    "git",
+    "go",
+    "grep",
+    "head",
+    "kill",
+    "kubectl",
+    "ln",
+    "ls",
+    "make",
+    "mkdir",
+    "mv",
+    "npm",
+    "npx",
+    "pip",
+    "pip3",
+    "ps",
+    "python",
+    "python3",
+    "rm",
+    "rustup",
+    "scp",
+    "sed",
+    "ssh",
+    "sudo",
+    "systemctl",
+    "tail",
+    "tar",
+    "touch",
+    "wget",
+    "yarn",
+];
+
+lazy_static! {
+    static ref URL: Regex = Regex::new(r"^(?:[a-zA-Z][a-zA-Z0-9+.-]*://|www\.)\S+$").unwrap();
+    static ref EMAIL: Regex = Regex::new(
+        r"^(?:mailto:)?[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}$"
+    )
+    .unwrap();
+    static ref COLOR: Regex = Regex::new(concat!(
+        r"^(?:#(?:[0-9a-fA-F]{3,4}|[0-9a-fA-F]{6}|[0-9a-fA-F]{8})",
+        r"|(?i:rgba?|hsla?)\(\s*[\d.]+(?:%|deg)?(?:\s*[,/ ]\s*[\d.]+%?){2,3}\s*\))$"
+    ))
+    .unwrap();
+    static ref NUMBER: Regex =
+        Regex::new(r"^[-+]?(?:\d{1,3}(?:,\d{3})+|\d+)?(?:\.\d+)?(?:[eE][-+]?\d+)?%?$").unwrap();
+    static ref PHONE: Regex = Regex::new(r"^\+?\(?\d[\d\s().-]{5,18}\d$").unwrap();
+    static ref PATH: Regex = Regex::new(concat!(
+        r"^(?:(?:~|\.{1,2})?(?:/[^/\s][^/\n]*)+/?|/",
+        r"|[A-Za-z]:\\[^\n]*|\\\\[^\\\s]+\\[^\n]*)$"
+    ))
+    .unwrap();
+    static ref LANGUAGES: Vec<(&'static str, Vec<Regex>)> = {
+        let languages = vec![
+            (
+                "rust",
+                vec![
+                    r"\bfn \w+",
+                    r"\blet mut\b",
+                    r"\bimpl\b",
+                    r"\buse \w+::",
+                    r"\w::\w",
+                    r"\bpub (?:fn|struct|enum)\b",
+                    r"println!\(",
+                ],
+            ),
+            (
+                "python",
+                vec![
+                    r"(?m)^\s*def \w+\(.*\):",
+                    r"(?m)^\s*(?:import \w+|from \w+ import)",
+                    r"\bself\.",
+                    r"\belif\b",
+                    r"(?m):\s*$",
+                    r"\bNone\b",
+                ],
+            ),
+            (
+                "javascript",
+                vec![
+                    r"\bconst \w+ =",
+                    r"\bfunction\b",
+                    r"=>",
+                    r"console\.log",
+                    r"\b(?:let|var) \w+ =",
+                    r"===",
+                    r"\brequire\(",
+                ],
+            ),
+            (
+                "java",
+                vec![
+                    r"\bpublic (?:static )?(?:class|void)\b",
+                    r"System\.out\.",
+                    r"\bprivate \w+",
+                    r"@Override",
+                    r"\bnew \w+\(",
+                ],
+            ),
+            (
+                "c",
+                vec![
+                    r"#include\s*<",
+                    r"\bprintf\(",
+                    r"\bint main\(",
+                    r"\bstd::",
+                    r"->\w+",
+                    r"\bmalloc\(",
+                ],
+            ),
+            (
+                "go",
+                vec![
+                    r"(?m)^package \w+",
+                    r"\bfunc \w*\(",
+                    r":=",
+                    r"\bfmt\.",
+                    r"\bgo func\b",
+                ],
+            ),
+            (
+                "sql",
+                vec![
+                    r"(?i)\bselect\b[\s\S]+\bfrom\b",
+                    r"(?i)\binsert into\b",
+                    r"(?i)\bcreate table\b",
+                    r"(?i)\bwhere\b",
+                    r"(?i)\b(?:inner|left|right) join\b",
+                ],
+            ),
+            (
+                "html",
+                vec![r"<(?:div|span|p|a|ul|li|html|body|head)\b[^>]*>", r"</\w+>"],
+            ),
+            (
+                "css",
+                vec![
+                    r"(?m)^\s*[.#]?[\w-]+[^{\n]*\{",
+                    r"(?m)^\s*[\w-]+\s*:\s*[^;\n]+;",
+                ],
+            ),
+            (
+                "shell",
+                vec![
+                    r"^#!/(?:usr/)?bin/(?:env )?(?:ba|z)?sh",
+                    r"\$\{?\w+\}?",
+                    r"(?m)^\s*(?:if|then|fi|done|esac)\b",
+                ],
+            ),
+        ];
+        languages
+            .into_iter()
+            .map(|(name, patterns)| {
+                let patterns = patterns
+                    .into_iter()
+                    .map(|pattern| Regex::new(pattern).unwrap())
+                    .collect();
+                (name, patterns)
+            })
+            .collect()
+    };
+}
+
+/// Guesses the kind of the text and, for code, its language. Returns `None`
+/// when the text is empty.
+pub fn classify(text: &str) -> Option<(Kind, Option<&'static str>)> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    let single_line = !text.contains('\n');
+    let kind = if single_line && URL.is_match(text) {
+        Kind::Url
+    } else if single_line && EMAIL.is_match(text) {
+        Kind::Email
+    } else if single_line && COLOR.is_match(text) {
+        Kind::Color
+    } else if single_line && is_date(text) {
+        Kind::Date
+    } else if single_line && text.chars().any(|c| c.is_ascii_digit()) && NUMBER.is_match(text) {
+        Kind::Number
+    } else if single_line && is_phone(text) {
+        Kind::Phone
+    } else if single_line && PATH.is_match(text) {
+        Kind::Path
+    } else if (text.starts_with('{') || text.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(text).is_ok()
+    {
+        Kind::Json
+    } else if is_command(text) {
+        Kind::Command
+    } else {
+        return Some(match guess_language(text) {
+            Some(language) => (Kind::Code, Some(language)),
+            None if looks_like_code(text) => (Kind::Code, None),
+            None => (Kind::Prose, None),
+        });
+    };
+    Some((kind, None))
+}
+
+fn is_date(text: &str) -> bool {
+    const DATES: &[&str] = &[
+        "%Y-%m-%d",
+        "%Y/%m/%d",
+        "%d/%m/%Y",
+        "%m/%d/%Y",
+        "%d.%m.%Y",
+        "%B %d, %Y",
+        "%b %d, %Y",
+        "%d %B %Y",
+        "%d %b %Y",
+        "%A, %B %d, %Y",
+    ];
+    const DATE_TIMES: &[&str] = &[
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S%.f",
+    ];
+    DateTime::parse_from_rfc3339(text).is_ok()
+        || DateTime::parse_from_rfc2822(text).is_ok()
+        || DATES
+            .iter()
+            .any(|format| NaiveDate::parse_from_str(text, format).is_ok())
+        || DATE_TIMES
+            .iter()
+            .any(|format| NaiveDateTime::parse_from_str(text, format).is_ok())
+}
+
+fn is_phone(text: &str) -> bool {
+    let digits = text.chars().filter(|c| c.is_ascii_digit()).count();
+    // Without a `+` or a separator it is just a number
+    PHONE.is_match(text)
+        && (7..=15).contains(&digits)
+        && (text.starts_with('+') || text.chars().any(|c| " ().-".contains(c)))
+}
+
+/// Every line starts with a known program or a `$` prompt. Lines continued
+/// with a trailing backslash belong to the previous command.
+fn is_command(text: &str) -> bool {
+    let mut continued = false;
+    let mut commands = 0;
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if !continued {
+            let line = line.strip_prefix("$ ").unwrap_or(line);
+            let program = line.split_whitespace().next().unwrap_or_default();
+            if !COMMANDS.contains(&program) {
+                return false;
+            }
+            commands += 1;
+        }
+        continued = line.ends_with('\\');
+    }
+    commands > 0 && commands <= 10
+}
+
+/// Picks the language with the most matching patterns, requiring at least two
+/// matches so that a single `=>` in a sentence does not make it code.
+fn guess_language(text: &str) -> Option<&'static str> {
+    LANGUAGES
+        .iter()
+        .map(|(name, patterns)| {
+            let score = patterns.iter().filter(|re| re.is_match(text)).count();
+            (score, *name)
+        })
+        .filter(|(score, _)| *score >= 2)
+        .max_by_key(|(score, _)| *score)
+        .map(|(_, name)| name)
+}
+
+/// Multi-line text where most lines end like statements or blocks do.
+fn looks_like_code(text: &str) -> bool {
+    let lines = text
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    let code_lines = lines
+        .iter()
+        .filter(|line| line.ends_with(|c| ";{}()".contains(c)))
+        .count();
+    lines.len() > 1 && code_lines * 2 > lines.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(text: &str) -> Kind {
+        classify(text).unwrap().0
+    }
+
+    #[test]
+    fn test_classify_single_values() {
+        assert_eq!(kind("https://example.com/a?b=c"), Kind::Url);
+        assert_eq!(kind("jane.doe@example.co.uk"), Kind::Email);
+        assert_eq!(kind("/home/user/My Documents/notes.txt"), Kind::Path);
+        assert_eq!(kind(r"C:\Users\jane\Desktop"), Kind::Path);
+        assert_eq!(kind("+1 (555) 123-4567"), Kind::Phone);
+        assert_eq!(kind("#ff8800"), Kind::Color);
+        assert_eq!(kind("rgb(255, 136, 0)"), Kind::Color);
+        assert_eq!(kind("-1,234.56"), Kind::Number);
+        assert_eq!(kind("2024-03-01"), Kind::Date);
+        assert_eq!(kind("2024-03-01T12:30:00Z"), Kind::Date);
+        assert_eq!(kind(r#"{"a": [1, 2]}"#), Kind::Json);
+        assert_eq!(kind("$ git commit -am 'fix' && git push"), Kind::Command);
+        assert_eq!(
+            kind("Meet me at the station, I will bring the tickets."),
+            Kind::Prose
+        );
+        assert_eq!(classify("  \n"), None);
+    }
+
+    #[test]
+    fn test_classify_code() {
+        let rust = "fn main() {\n    let mut v = Vec::new();\n    v.push(1);\n}";
+        assert_eq!(classify(rust), Some((Kind::Code, Some("rust"))));
+        let python = "def add(a, b):\n    return a + b\n\nimport os";
+        assert_eq!(classify(python), Some((Kind::Code, Some("python"))));
+        assert_eq!(
+            Stats::of(python),
+            Stats {
+                chars: 42,
+                words: 9,
+                lines: 4
+            }
+        );
+    }
+}
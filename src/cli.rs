@@ -0,0 +1,74 @@
+use clipboard2json::BackendKind;
+use failure::{bail, format_err, Error};
+use std::path::PathBuf;
+
+/// Options passed to the program on the command line.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Clipboard backend to watch, overrides the one in the config file
+    pub backend: Option<BackendKind>,
+    /// Path of the config file, overrides the default location
+    pub config: Option<PathBuf>,
+    /// Directory of the history, overrides the environment and the config
+    pub data_dir: Option<PathBuf>,
+    /// Whether `--prune` was passed
+    pub prune: bool,
+    /// Whether `--rekey` was passed
+    pub rekey: bool,
+    /// Whether `--help` was passed
+    pub help: bool,
+}
+
+pub fn usage() -> String {
+    format!(
+        "Usage: clipboard2json [OPTIONS]
+
+Options:
+    --backend <BACKEND>    Clipboard backend to use [{}] (default: auto)
+    --config <FILE>        Path of the JSON config file
+    --data-dir <DIR>       Directory where the history is stored (default:
+                           $XDG_DATA_HOME/clipboard2json)
+    --prune                Apply the retention limits of the sinks and exit
+    --rekey                Re-encrypt the file sinks with the passphrase in
+                           CLIPBOARD2JSON_NEW_PASSPHRASE and exit
+    -h, --help             Print this help message",
+        BackendKind::NAMES.join(", ")
+    )
+}
+
+/// Parses the command line arguments, excluding the program name. Options
+/// that take a value accept both `--name value` and `--name=value`.
+pub fn parse<I>(args: I) -> Result<Options, Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => {
+                (arg[..i].to_string(), Some(arg[i + 1..].to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format_err!("Missing value for {}", name))
+        };
+
+        match name.as_str() {
+            "--backend" => options.backend = Some(value()?.parse()?),
+            "--config" => options.config = Some(PathBuf::from(value()?)),
+            "--data-dir" => options.data_dir = Some(PathBuf::from(value()?)),
+            "--prune" => options.prune = true,
+            "--rekey" => options.rekey = true,
+            "-h" | "--help" => options.help = true,
+            _ => bail!("Unknown argument '{}'\n\n{}", arg, usage()),
+        }
+    }
+
+    Ok(options)
+}
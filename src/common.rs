@@ -1,7 +1,19 @@
+use crate::blobs::BlobRef;
+use crate::classify::{classify, Kind, Stats};
+use crate::html::{self, Image, Link};
+use crate::logs::{parse_log, LogEntry};
+use crate::redact::Detector;
+use crate::structured::{parse_document, Format};
+use crate::table::Table;
+use crate::trace::{parse_stack_trace, StackTrace};
+use crate::urls::{clean_url, UrlParts};
 use failure::Error;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Defines common traits for the clipboard so that it's easier to abstract over
 /// the underlying libraries.
@@ -12,22 +24,125 @@ pub trait ClipboardFunctions {
     fn get_clipboard(&self) -> Result<ClipboardData, Error>;
     /// Watches over the clipboard and passes the changed data to the callback
     fn watch_clipboard(&self, callback: &ClipboardSink);
+    /// Watches over the selections in the options and passes the changed data
+    /// to the callback once each selection has stopped changing for its quiet
+    /// window. Backends that cannot debounce or watch other selections fall
+    /// back to `watch_clipboard`.
+    fn watch_selections(&self, callback: &ClipboardSink, options: &WatchOptions) {
+        let _ = options;
+        self.watch_clipboard(callback)
+    }
+    /// Gets a function that replaces the clipboard with a text, for backends
+    /// that can write to the clipboard from within the watch callback.
+    fn writer(&self) -> Option<ClipboardWriter> {
+        None
+    }
+}
+
+/// The selections that can hold copied data. Only X11 and some Wayland
+/// compositors have a primary selection, which holds the currently selected
+/// text and changes continuously while the user drags the selection.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Selection {
+    Clipboard,
+    Primary,
+}
+
+/// Describes which selections are watched and how long each of them has to
+/// stay unchanged before it is read.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchOptions {
+    pub selections: Vec<SelectionOptions>,
+}
+
+impl Default for WatchOptions {
+    /// Only watches the clipboard with a short quiet window which is enough
+    /// for the applications that set the clipboard several times per copy.
+    fn default() -> Self {
+        WatchOptions {
+            selections: vec![SelectionOptions {
+                selection: Selection::Clipboard,
+                debounce_ms: 100,
+            }],
+        }
+    }
+}
+
+impl WatchOptions {
+    /// Gets the quiet window of every watched selection.
+    pub fn windows(&self) -> Vec<(Selection, Duration)> {
+        self.selections
+            .iter()
+            .map(|options| {
+                (
+                    options.selection,
+                    Duration::from_millis(options.debounce_ms),
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SelectionOptions {
+    pub selection: Selection,
+    /// Milliseconds without changes before the selection is read
+    #[serde(default)]
+    pub debounce_ms: u64,
 }
 
 /// Stores a function that takes the clipboard data and writes it to a source.
 /// It is stored in a struct because it is easier to implement Clone this way which
 /// plays nicely with the static variables in the WinAPI implementation of the
-/// clipboard.
+/// clipboard. The function is reference counted so that closures holding state,
+/// such as a `Pipeline`, can be used as well as plain functions.
+#[derive(Clone)]
+pub struct ClipboardSink(pub Arc<dyn Fn(ClipboardData) -> Result<(), Error> + Send + Sync>);
+
+impl ClipboardSink {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(ClipboardData) -> Result<(), Error> + Send + Sync + 'static,
+    {
+        ClipboardSink(Arc::new(f))
+    }
+}
+
+/// Stores a function that replaces the content of the clipboard with a text.
+/// Like `ClipboardSink` it is reference counted so that it can be cloned into
+/// the pipeline stages that write back to the clipboard.
 #[derive(Clone)]
-pub struct ClipboardSink(pub fn(ClipboardData) -> Result<(), Error>);
+pub struct ClipboardWriter(pub Arc<WriteFn>);
+
+type WriteFn = dyn Fn(&str) -> Result<(), Error> + Send + Sync;
+
+impl ClipboardWriter {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&str) -> Result<(), Error> + Send + Sync + 'static,
+    {
+        ClipboardWriter(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for ClipboardWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ClipboardWriter")
+    }
+}
 
 /// Represents the different clipboard format target available in WinAPI and X11.
 /// Both allow to get the target identifier along with their name but somewhat
-/// differ in their representation of it.
+/// differ in their representation of it. Backends without target identifiers,
+/// such as Wayland, only list the MIME types.
 #[derive(Debug)]
 pub enum ClipboardTargets {
     WINAPI(HashMap<String, u32>),
     X11(HashMap<String, u64>),
+    MIME(Vec<String>),
 }
 
 /// Represents the textual data stored in clipboard as either HTML or UTF8.  
@@ -37,23 +152,147 @@ pub enum ClipboardTargets {
 /// content differently. Also, the clipboard owner can convert types such as images
 /// to an img tag with the URL for the image.Unlike the Win API, there does not
 /// seem to be an easy way of getting the URL of the HTML document in X11.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum ClipboardData {
     Html {
         content: String,
         owner: Option<String>,
         url: Option<String>,
+        /// The fragment rendered as plain text
+        #[serde(default)]
+        text: String,
+        /// The fragment converted to Markdown, when enabled in the pipeline
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        markdown: Option<String>,
+        /// Links in the fragment, resolved against `url` when it is known
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        links: Vec<Link>,
+        /// Images in the fragment, resolved against `url` when it is known
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        images: Vec<Image>,
         created_at: u64,
+        #[serde(flatten)]
+        meta: Metadata,
     },
     #[serde(rename = "text")]
     UnicodeText {
         content: String,
         owner: Option<String>,
         created_at: u64,
+        #[serde(flatten)]
+        meta: Metadata,
     },
 }
 
+/// Extra information stored alongside every kind of clipboard data.
+///
+/// The fields are flattened into the record when it is serialized and all of
+/// them have defaults so that histories written by older versions still load.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Metadata {
+    /// Hex encoded SHA-256 hash of the content, used to detect repeated copies
+    #[serde(default)]
+    pub content_hash: String,
+    /// Number of times the same content was copied
+    #[serde(default = "default_copy_count")]
+    pub copy_count: u32,
+    /// Timestamp in seconds of the latest copy when it was copied more than once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_copied_at: Option<u64>,
+    /// Selection the data was read from, when the backend has more than one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selection: Option<Selection>,
+    /// Window class of the owner, i.e. `WM_CLASS` on X11 and the registered
+    /// class name on Windows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_class: Option<String>,
+    /// Detectors that found secrets in the content before it was redacted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redactions: Vec<Detector>,
+    /// What the content looks like, e.g. a URL, a date or code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<Kind>,
+    /// Guessed programming language when the content is code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Stats>,
+    /// Rows of the table when the content is an HTML table or tab or comma
+    /// separated values
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table: Option<Table>,
+    /// Format of the document when the content is JSON, YAML, TOML or XML
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_format: Option<Format>,
+    /// The document converted to JSON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parsed: Option<serde_json::Value>,
+    /// Why the document could not be parsed when it looks like one of the
+    /// formats but is not valid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parse_error: Option<String>,
+    /// Rust panic, Python traceback or Java exception found in the content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack_trace: Option<StackTrace>,
+    /// Lines of the content when it is a log excerpt
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub log: Vec<LogEntry>,
+    /// Scheme, host, path and query of the content when it is a URL. Not
+    /// named `url` since the fields are flattened into the record, where
+    /// `url` is already the address of the page that HTML was copied from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_parts: Option<UrlParts>,
+    /// The URL without its tracking parameters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clean_url: Option<String>,
+    /// Content as it was copied, when a stage such as the terminal cleanup
+    /// changed it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+    /// Where the content is stored when it was moved to the blob store
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<BlobRef>,
+}
+
+impl Metadata {
+    fn new() -> Self {
+        Metadata {
+            content_hash: String::new(),
+            copy_count: 1,
+            last_copied_at: None,
+            selection: None,
+            owner_class: None,
+            redactions: vec![],
+            kind: None,
+            language: None,
+            stats: None,
+            table: None,
+            source_format: None,
+            parsed: None,
+            parse_error: None,
+            stack_trace: None,
+            log: vec![],
+            url_parts: None,
+            clean_url: None,
+            raw: None,
+            blob: None,
+        }
+    }
+}
+
+fn default_copy_count() -> u32 {
+    1
+}
+
+/// Hashes the content into a lowercase hex encoded SHA-256 digest.
+pub fn hash_content(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
 impl ClipboardData {
     /// Helper function for getting the content stored for testing purpose
     #[allow(dead_code)]
@@ -63,26 +302,156 @@ impl ClipboardData {
             ClipboardData::UnicodeText { content, .. } => content.to_string(),
         }
     }
+
+    /// Gets the HTML fragment or the text that was copied.
+    pub fn content(&self) -> &str {
+        match self {
+            ClipboardData::Html { content, .. } | ClipboardData::UnicodeText { content, .. } => {
+                content
+            }
+        }
+    }
+
+    /// Gets a mutable reference to the copied content so that pipeline stages
+    /// can transform it.
+    pub fn content_mut(&mut self) -> &mut String {
+        match self {
+            ClipboardData::Html { content, .. } | ClipboardData::UnicodeText { content, .. } => {
+                content
+            }
+        }
+    }
+
+    /// Gets the name of the type as it is written in the `type` field.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ClipboardData::Html { .. } => "html",
+            ClipboardData::UnicodeText { .. } => "text",
+        }
+    }
+
+    /// Gets the timestamp in seconds of when the data was copied.
+    pub fn created_at(&self) -> u64 {
+        match self {
+            ClipboardData::Html { created_at, .. }
+            | ClipboardData::UnicodeText { created_at, .. } => *created_at,
+        }
+    }
+
+    pub fn meta(&self) -> &Metadata {
+        match self {
+            ClipboardData::Html { meta, .. } | ClipboardData::UnicodeText { meta, .. } => meta,
+        }
+    }
+
+    pub fn meta_mut(&mut self) -> &mut Metadata {
+        match self {
+            ClipboardData::Html { meta, .. } | ClipboardData::UnicodeText { meta, .. } => meta,
+        }
+    }
+
+    /// Recomputes the content hash after the content has been modified.
+    /// Records loaded from older histories do not have a hash either.
+    pub fn refresh_hash(&mut self) {
+        let hash = hash_content(self.content());
+        self.meta_mut().content_hash = hash;
+    }
+
+    /// Recomputes the hash, the kind and the stats of the content. HTML is
+    /// rendered as text first, which is stored in the `text` field, and its
+    /// links and images are extracted. Tables, documents such as JSON or YAML,
+    /// stack traces, logs and URLs are parsed from both kinds.
+    pub fn refresh(&mut self) {
+        self.refresh_hash();
+        let (text, table) = match self {
+            ClipboardData::Html {
+                content,
+                url,
+                text,
+                markdown,
+                links,
+                images,
+                ..
+            } => {
+                *text = html::to_text(content);
+                let (found_links, found_images) = html::extract_resources(content, url.as_deref());
+                *links = found_links;
+                *images = found_images;
+                if let Some(markdown) = markdown {
+                    *markdown = html::to_markdown(content);
+                }
+                (text.clone(), html::extract_table(content))
+            }
+            ClipboardData::UnicodeText { content, .. } => {
+                (content.clone(), Table::from_text(content))
+            }
+        };
+        let classification = classify(&text);
+        let meta = self.meta_mut();
+        meta.kind = classification.map(|(kind, _)| kind);
+        meta.language = classification.and_then(|(_, language)| language.map(String::from));
+        meta.stats = Some(Stats::of(&text));
+        meta.table = table;
+        let document = parse_document(&text);
+        meta.source_format = document.as_ref().map(|(format, _)| *format);
+        let result = document.map(|(_, result)| result);
+        meta.parsed = result.clone().and_then(Result::ok);
+        meta.parse_error = result.and_then(Result::err);
+        meta.stack_trace = parse_stack_trace(&text);
+        meta.log = parse_log(&text);
+        let url = Some(text.as_str()).filter(|_| meta.kind == Some(Kind::Url));
+        meta.url_parts = url.and_then(UrlParts::parse);
+        meta.clean_url = url.and_then(clean_url);
+    }
+
+    /// Fills the `markdown` field of HTML data. Text data is left unchanged.
+    pub fn render_markdown(&mut self) {
+        if let ClipboardData::Html {
+            content, markdown, ..
+        } = self
+        {
+            *markdown = Some(html::to_markdown(content));
+        }
+    }
+
+    /// Gets the title of the window that owned the clipboard, if known.
+    pub fn owner(&self) -> Option<&str> {
+        match self {
+            ClipboardData::Html { owner, .. } | ClipboardData::UnicodeText { owner, .. } => {
+                owner.as_ref().map(String::as_str)
+            }
+        }
+    }
 }
 
 impl From<(String, Option<String>, Option<String>)> for ClipboardData {
     fn from((content, owner, url): (String, Option<String>, Option<String>)) -> ClipboardData {
-        ClipboardData::Html {
+        let mut data = ClipboardData::Html {
+            meta: Metadata::new(),
             content,
             owner,
             url,
+            text: String::new(),
+            markdown: None,
+            links: vec![],
+            images: vec![],
             created_at: get_created_timestamp(),
-        }
+        };
+        data.refresh();
+        data
     }
 }
 
 impl From<(String, Option<String>)> for ClipboardData {
     fn from((content, owner): (String, Option<String>)) -> ClipboardData {
-        ClipboardData::UnicodeText {
+        let mut data = ClipboardData::UnicodeText {
+            meta: Metadata::new(),
             content,
             owner,
             created_at: get_created_timestamp(),
-        }
+        };
+        data.refresh();
+        data
     }
 }
 
@@ -97,7 +466,7 @@ impl ClipboardData {
 
 /// Helper function for getting the timestamp in milliseconds when the
 /// `ClipboardData` enum is created.
-fn get_created_timestamp() -> u64 {
+pub(crate) fn get_created_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("Oops went back in time")
@@ -107,7 +476,6 @@ fn get_created_timestamp() -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Clipboard;
     use clipboard::{ClipboardContext, ClipboardProvider};
 
     // Cannot set contents in X11 Clipboard
@@ -115,8 +483,10 @@ mod tests {
     #[test]
     #[cfg(windows)]
     fn test_get_clipboard_text() {
+        use crate::{BackendKind, Clipboard};
+
         let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
-        let clipboard = Clipboard::new().unwrap();
+        let clipboard = Clipboard::open(BackendKind::Auto).unwrap();
         let data = "This is a normal string";
         ctx.set_contents(data.to_string()).unwrap();
         let clipboard_data = clipboard.get_clipboard().unwrap().get_content();
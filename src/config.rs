@@ -0,0 +1,454 @@
+use crate::backend::BackendKind;
+use crate::common::WatchOptions;
+use crate::crypto::{Cipher, PASSPHRASE_VAR};
+use crate::dedup::DedupMode;
+use crate::filter::FilterRules;
+use crate::maildir::MaildirSink;
+use crate::pipeline::Sink;
+use crate::redact::Redactor;
+use crate::retention::Retention;
+use crate::sanitize::Sanitizer;
+use crate::segments::Rollover;
+use crate::sinks::{FileSink, HttpSink, StdoutSink};
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteSink;
+use crate::store::{create_private_dir, extra_permissions, restrict_permissions};
+use crate::terminal::TerminalCleanup;
+use crate::urls::UrlCleaner;
+use failure::{bail, format_err, Error};
+use serde::{Deserialize, Deserializer};
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Environment variable overriding the data directory of the config.
+pub const DATA_DIR_VAR: &str = "CLIPBOARD2JSON_DATA_DIR";
+
+/// Settings read from the JSON config file.
+///
+/// Every field is optional so that an empty object is a valid config which
+/// behaves like running the program without any config file.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Clipboard backend used when `--backend` is not passed
+    #[serde(deserialize_with = "deserialize_backend")]
+    pub backend: Option<BackendKind>,
+    /// Selections to watch and their debounce windows
+    pub watch: WatchOptions,
+    /// Directory that relative sink paths are resolved against
+    pub data_dir: Option<PathBuf>,
+    pub pipeline: PipelineConfig,
+}
+
+/// Describes the stages and sinks that the clipboard data is passed through.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub stages: Vec<StageConfig>,
+    pub sinks: Vec<SinkConfig>,
+}
+
+impl Default for PipelineConfig {
+    /// Writes the history to `clipboard.json` in the data directory.
+    fn default() -> Self {
+        PipelineConfig {
+            stages: vec![],
+            sinks: vec![SinkConfig::File {
+                path: PathBuf::from("clipboard.json"),
+                dedup: DedupMode::default(),
+                retention: Retention::default(),
+                rollover: Rollover::default(),
+                blob_threshold: None,
+                encrypt: false,
+            }],
+        }
+    }
+}
+
+impl PipelineConfig {
+    /// Fails when a file sink is encrypted while another sink stores the
+    /// same records in plain text, which would defeat the encryption.
+    pub fn check_encryption(&self) -> Result<(), Error> {
+        let encrypted = self
+            .sinks
+            .iter()
+            .any(|sink| matches!(sink, SinkConfig::File { encrypt: true, .. }));
+        let plain = self.sinks.iter().find_map(SinkConfig::plain_text_path);
+        if let (true, Some(path)) = (encrypted, plain) {
+            bail!(
+                "The history is encrypted but {} would store it in plain text",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The built-in stages that can be enabled from the config file.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum StageConfig {
+    /// Removes leading and trailing whitespace from the content
+    Trim,
+    /// Drops the data when the content is only whitespace
+    SkipEmpty,
+    /// Only lets through the data allowed by the include and exclude rules
+    Filter(FilterRules),
+    /// Adds the Markdown conversion of HTML data
+    Markdown,
+    /// Cleans up HTML data with an allow-list policy
+    Sanitize(Sanitizer),
+    /// Masks, hashes or drops the data containing secrets
+    Redact(Redactor),
+    /// Strips escape sequences and prompts from text copied from a terminal
+    Terminal(TerminalCleanup),
+    /// Removes the tracking parameters from copied URLs
+    CleanUrl(UrlCleaner),
+}
+
+/// The built-in sinks that can be enabled from the config file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum SinkConfig {
+    File {
+        path: PathBuf,
+        #[serde(default)]
+        dedup: DedupMode,
+        #[serde(default)]
+        retention: Retention,
+        #[serde(default)]
+        rollover: Rollover,
+        /// Content larger than this many bytes is moved to the blob store
+        #[serde(default)]
+        blob_threshold: Option<u64>,
+        /// Encrypts the records with the passphrase in `CLIPBOARD2JSON_PASSPHRASE`
+        #[serde(default)]
+        encrypt: bool,
+    },
+    /// Writes every record to a file of its own in a Maildir-like directory
+    Maildir {
+        path: PathBuf,
+        /// Only accepted to be rejected, since the records are not encrypted
+        #[serde(default)]
+        encrypt: bool,
+    },
+    Stdout,
+    Http {
+        url: String,
+    },
+    #[cfg(feature = "sqlite")]
+    Sqlite {
+        path: PathBuf,
+        #[serde(default)]
+        dedup: DedupMode,
+        #[serde(default)]
+        retention: Retention,
+        /// Only accepted to be rejected, since the records are not encrypted
+        #[serde(default)]
+        encrypt: bool,
+    },
+}
+
+impl SinkConfig {
+    /// Creates the sink described by the config.
+    pub fn build(&self) -> Result<Box<dyn Sink>, Error> {
+        Ok(match self {
+            SinkConfig::File {
+                path,
+                dedup,
+                retention,
+                rollover,
+                blob_threshold,
+                encrypt,
+            } => {
+                let mut sink = FileSink::with_dedup(path.clone(), *dedup)
+                    .with_retention(retention.clone())
+                    .with_rollover(rollover.clone());
+                if let Some(threshold) = blob_threshold {
+                    // The names of the blobs would give away the hashes
+                    if *encrypt {
+                        bail!("The blob store of {} cannot be encrypted", path.display());
+                    }
+                    sink = sink.with_blob_threshold(*threshold);
+                }
+                if *encrypt {
+                    let cipher = Cipher::from_env(PASSPHRASE_VAR)?.ok_or_else(|| {
+                        format_err!("Set {} to encrypt {}", PASSPHRASE_VAR, path.display())
+                    })?;
+                    sink = sink.with_cipher(cipher);
+                }
+                Box::new(sink)
+            }
+            SinkConfig::Maildir { path, encrypt } => {
+                if *encrypt {
+                    bail!("The maildir sink {} cannot be encrypted", path.display());
+                }
+                Box::new(MaildirSink::new(path.clone()))
+            }
+            SinkConfig::Stdout => Box::new(StdoutSink),
+            SinkConfig::Http { url } => Box::new(HttpSink::new(url)?),
+            #[cfg(feature = "sqlite")]
+            SinkConfig::Sqlite {
+                path,
+                dedup,
+                retention,
+                encrypt,
+            } => {
+                if *encrypt {
+                    bail!("The SQLite sink {} cannot be encrypted", path.display());
+                }
+                Box::new(
+                    SqliteSink::with_dedup(path.clone(), *dedup)?.with_retention(retention.clone()),
+                )
+            }
+        })
+    }
+
+    /// Where the sink stores the history on the disk in plain text, if it
+    /// does.
+    fn plain_text_path(&self) -> Option<&Path> {
+        match self {
+            SinkConfig::File {
+                path,
+                encrypt: false,
+                ..
+            }
+            | SinkConfig::Maildir { path, .. } => Some(path),
+            #[cfg(feature = "sqlite")]
+            SinkConfig::Sqlite { path, .. } => Some(path),
+            SinkConfig::File { .. } | SinkConfig::Stdout | SinkConfig::Http { .. } => None,
+        }
+    }
+
+    /// Path of the history stored by the sink, if any.
+    fn path_mut(&mut self) -> Option<&mut PathBuf> {
+        match self {
+            SinkConfig::File { path, .. } | SinkConfig::Maildir { path, .. } => Some(path),
+            #[cfg(feature = "sqlite")]
+            SinkConfig::Sqlite { path, .. } => Some(path),
+            SinkConfig::Stdout | SinkConfig::Http { .. } => None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config from a JSON file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|e| format_err!("Could not open config {}: {}", path.display(), e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format_err!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// Picks the data directory, from the first of the `--data-dir` flag, the
+    /// `CLIPBOARD2JSON_DATA_DIR` environment variable, the `data_dir` of the
+    /// config and `default_data_dir` that is set, and resolves the relative
+    /// paths of the sinks against it. A missing directory is created so that
+    /// only the user can access it. The default directory is restricted the
+    /// same way when it exists, while for a directory that was picked by the
+    /// user, which may be shared, a warning is printed instead.
+    pub fn resolve_data_dir(&mut self, flag: Option<PathBuf>) -> Result<PathBuf, Error> {
+        let picked = flag
+            .or_else(|| {
+                env::var_os(DATA_DIR_VAR)
+                    .filter(|dir| !dir.is_empty())
+                    .map(PathBuf::from)
+            })
+            .or_else(|| self.data_dir.clone());
+        let is_default = picked.is_none();
+        let dir = picked
+            .or_else(Config::default_data_dir)
+            .ok_or_else(|| format_err!("Could not find the data directory, pass --data-dir"))?;
+        let existed = dir.exists();
+        create_private_dir(&dir)
+            .and_then(|_| {
+                if is_default {
+                    restrict_permissions(&dir, 0o700)
+                } else {
+                    Ok(())
+                }
+            })
+            .map_err(|e| format_err!("Could not create {}: {}", dir.display(), e))?;
+        if existed && !is_default && extra_permissions(&dir, 0o700)? != 0 {
+            eprintln!(
+                "WARNING: Other users can access the data directory {}, only the history \
+                 files are kept private.",
+                dir.display()
+            );
+        }
+        for path in self
+            .pipeline
+            .sinks
+            .iter_mut()
+            .filter_map(SinkConfig::path_mut)
+        {
+            if path.is_relative() {
+                *path = dir.join(&path);
+            }
+        }
+        Ok(dir)
+    }
+
+    /// Directory where the history is stored by default, which is
+    /// `$XDG_DATA_HOME/clipboard2json` on Linux and
+    /// `%LOCALAPPDATA%\clipboard2json` on Windows.
+    pub fn default_data_dir() -> Option<PathBuf> {
+        let data_dir = if cfg!(windows) {
+            env::var_os("LOCALAPPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_DATA_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| {
+                    env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("share"))
+                })
+        };
+        data_dir.map(|dir| dir.join("clipboard2json"))
+    }
+
+    /// Location of the config file for the current user, which is
+    /// `$XDG_CONFIG_HOME/clipboard2json/config.json` on Linux and
+    /// `%APPDATA%\clipboard2json\config.json` on Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        };
+        config_dir.map(|dir| dir.join("clipboard2json").join("config.json"))
+    }
+}
+
+fn deserialize_backend<'de, D>(deserializer: D) -> Result<Option<BackendKind>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name: Option<String> = Option::deserialize(deserializer)?;
+    name.map(|name| name.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Selection;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "backend": "headless",
+                "watch": { "selections": [{ "selection": "primary", "debounce_ms": 500 }] },
+                "pipeline": {
+                    "stages": [{ "type": "trim" }, { "type": "filter", "exclude": [{ "max_length": 2 }] },
+                               { "type": "redact", "detectors": ["jwt"], "action": "drop" }],
+                    "sinks": [{ "type": "stdout" }, { "type": "http", "url": "http://localhost/" }]
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.backend, Some(BackendKind::Headless));
+        assert_eq!(
+            config.watch.windows(),
+            vec![(Selection::Primary, Duration::from_millis(500))]
+        );
+        assert!(matches!(
+            config.pipeline.stages.as_slice(),
+            [
+                StageConfig::Trim,
+                StageConfig::Filter(_),
+                StageConfig::Redact(_)
+            ]
+        ));
+        assert_eq!(config.pipeline.sinks.len(), 2);
+    }
+
+    #[test]
+    fn test_resolve_data_dir() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "data_dir": "/ignored",
+                "pipeline": { "sinks": [{ "type": "file", "path": "clipboard.json" },
+                                        { "type": "file", "path": "/tmp/copies.json" }] }
+            }"#,
+        )
+        .unwrap();
+        let temp = tempfile::tempdir().unwrap();
+        let data = temp.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |mode| std::fs::Permissions::from_mode(mode);
+            // A directory picked by the user may be shared with others
+            std::fs::set_permissions(&data, mode(0o755)).unwrap();
+            config.resolve_data_dir(Some(data.clone())).unwrap();
+            let metadata = std::fs::metadata(&data).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o755);
+            let created = data.join("created");
+            config.resolve_data_dir(Some(created.clone())).unwrap();
+            let metadata = std::fs::metadata(&created).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o700);
+        }
+        let dir = config.resolve_data_dir(Some(data.clone())).unwrap();
+        assert_eq!(dir, data);
+        let paths = config
+            .pipeline
+            .sinks
+            .iter_mut()
+            .filter_map(|sink| sink.path_mut().cloned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                data.join("clipboard.json"),
+                PathBuf::from("/tmp/copies.json")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_config_writes_to_file() {
+        let config: Config = serde_json::from_str("{}").unwrap();
+        assert_eq!(
+            config.pipeline.sinks,
+            vec![SinkConfig::File {
+                path: PathBuf::from("clipboard.json"),
+                dedup: DedupMode::Merge,
+                retention: Retention::default(),
+                rollover: Rollover::default(),
+                blob_threshold: None,
+                encrypt: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_reject_plain_text_next_to_encrypted() {
+        let pipeline = |sinks: &str| -> PipelineConfig {
+            serde_json::from_str(&format!(r#"{{ "sinks": {} }}"#, sinks)).unwrap()
+        };
+        let sinks = r#"[{ "type": "maildir", "path": "clipboard", "encrypt": true }]"#;
+        assert!(pipeline(sinks).sinks[0].build().is_err());
+
+        let sinks = r#"[{ "type": "file", "path": "clipboard.json", "encrypt": true },
+                        { "type": "maildir", "path": "clipboard" }]"#;
+        let err = pipeline(sinks).check_encryption().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("clipboard would store it in plain text"));
+        let sinks = r#"[{ "type": "file", "path": "clipboard.json", "encrypt": true },
+                        { "type": "stdout" }]"#;
+        assert!(pipeline(sinks).check_encryption().is_ok());
+        let sinks = r#"[{ "type": "file", "path": "clipboard.json" },
+                        { "type": "maildir", "path": "clipboard" }]"#;
+        assert!(pipeline(sinks).check_encryption().is_ok());
+    }
+}
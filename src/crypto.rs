@@ -0,0 +1,241 @@
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use failure::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use zeroize::Zeroizing;
+
+/// Environment variable holding the passphrase of encrypted histories.
+pub const PASSPHRASE_VAR: &str = "CLIPBOARD2JSON_PASSPHRASE";
+/// Environment variable holding the passphrase that `--rekey` encrypts with.
+pub const NEW_PASSPHRASE_VAR: &str = "CLIPBOARD2JSON_NEW_PASSPHRASE";
+
+const SALT_LENGTH: usize = 16;
+
+type Key = Zeroizing<[u8; 32]>;
+
+/// Encrypts the records of a history with XChaCha20-Poly1305 and a key
+/// derived from a passphrase with Argon2id.
+///
+/// Every record is sealed on its own so that the history stays append-only.
+/// The salt of the key is stored with each record. The key of the first
+/// record that is decrypted is reused to encrypt new records, so that the
+/// passphrase only goes through Argon2 once per history.
+#[derive(Clone)]
+pub struct Cipher(Arc<Keys>);
+
+struct Keys {
+    passphrase: Zeroizing<String>,
+    /// Keys derived so far with their salts
+    derived: Mutex<Vec<(Vec<u8>, Key)>>,
+}
+
+/// A record as it is stored in an encrypted history.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Sealed {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl Cipher {
+    pub fn new(passphrase: &str) -> Result<Self, Error> {
+        if passphrase.is_empty() {
+            bail!("The passphrase cannot be empty");
+        }
+        Ok(Cipher(Arc::new(Keys {
+            passphrase: Zeroizing::new(passphrase.to_string()),
+            derived: Mutex::new(vec![]),
+        })))
+    }
+
+    /// Reads the passphrase from the environment variable. Returns `None`
+    /// when it is not set or empty.
+    pub fn from_env(name: &str) -> Result<Option<Self>, Error> {
+        match env::var(name) {
+            Ok(passphrase) if !passphrase.is_empty() => Cipher::new(&passphrase).map(Some),
+            Ok(_) | Err(env::VarError::NotPresent) => Ok(None),
+            Err(e) => bail!("Invalid {}: {}", name, e),
+        }
+    }
+
+    /// Encrypts the serialized record.
+    pub(crate) fn seal(&self, plaintext: &[u8]) -> Result<Sealed, Error> {
+        let (salt, key) = self.write_key()?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = XChaCha20Poly1305::new(key.as_ref().into())
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| format_err!("Could not encrypt the record"))?;
+        Ok(Sealed {
+            salt: BASE64.encode(salt),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        })
+    }
+
+    /// Decrypts a record, which fails when the passphrase is wrong or the
+    /// record was modified.
+    pub(crate) fn open(&self, sealed: &Sealed) -> Result<Zeroizing<Vec<u8>>, Error> {
+        let decode = |field: &str| {
+            BASE64
+                .decode(field)
+                .map_err(|e| format_err!("Invalid encrypted record: {}", e))
+        };
+        let salt = decode(&sealed.salt)?;
+        let nonce = decode(&sealed.nonce)?;
+        if nonce.len() != 24 {
+            bail!(
+                "Invalid encrypted record: the nonce is {} bytes",
+                nonce.len()
+            );
+        }
+        let key = self.key(&salt)?;
+        XChaCha20Poly1305::new(key.as_ref().into())
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                decode(&sealed.ciphertext)?.as_ref(),
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| {
+                format_err!(
+                    "Could not decrypt the history, the passphrase is wrong or it was modified"
+                )
+            })
+    }
+
+    fn key(&self, salt: &[u8]) -> Result<Key, Error> {
+        let mut derived = self.0.derived.lock().unwrap();
+        if let Some((_, key)) = derived.iter().find(|(known, _)| known.as_slice() == salt) {
+            return Ok(key.clone());
+        }
+        let key = self.derive(salt)?;
+        derived.push((salt.to_vec(), key.clone()));
+        Ok(key)
+    }
+
+    /// Key that new records are encrypted with, derived with a new salt when
+    /// no record was decrypted yet.
+    fn write_key(&self) -> Result<(Vec<u8>, Key), Error> {
+        if let Some(first) = self.0.derived.lock().unwrap().first() {
+            return Ok(first.clone());
+        }
+        let mut salt = vec![0; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        let key = self.key(&salt)?;
+        Ok((salt, key))
+    }
+
+    fn derive(&self, salt: &[u8]) -> Result<Key, Error> {
+        let mut key = Zeroizing::new([0; 32]);
+        Argon2::default()
+            .hash_password_into(self.0.passphrase.as_bytes(), salt, key.as_mut())
+            .map_err(|e| format_err!("Could not derive the key: {}", e))?;
+        Ok(key)
+    }
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Cipher { .. }")
+    }
+}
+
+/// Tells encrypted lines of a history from plain records without parsing
+/// them.
+pub(crate) fn is_sealed(line: &str) -> bool {
+    line.trim_start().starts_with("{\"salt\":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        assert!(Cipher::new("").is_err());
+        let cipher = Cipher::new("correct horse").unwrap();
+        let sealed = cipher.seal(b"hunter2").unwrap();
+        assert_eq!(cipher.open(&sealed).unwrap().as_slice(), b"hunter2");
+
+        // The key of the history is derived once and reused
+        let again = cipher.seal(b"hunter2").unwrap();
+        assert_eq!(again.salt, sealed.salt);
+        assert_ne!(again.nonce, sealed.nonce);
+        assert_ne!(again.ciphertext, sealed.ciphertext);
+        assert_eq!(cipher.0.derived.lock().unwrap().len(), 1);
+
+        // Another cipher with the same passphrase derives the same key
+        let other = Cipher::new("correct horse").unwrap();
+        assert_eq!(other.open(&sealed).unwrap().as_slice(), b"hunter2");
+        assert_eq!(
+            *other.key(&BASE64.decode(&sealed.salt).unwrap()).unwrap(),
+            *cipher.write_key().unwrap().1
+        );
+        assert!(Cipher::new("battery staple")
+            .unwrap()
+            .open(&sealed)
+            .is_err());
+    }
+
+    #[test]
+    fn test_tampered_record() {
+        let cipher = Cipher::new("correct horse").unwrap();
+        let sealed = cipher.seal(b"hunter2").unwrap();
+        let flip = |field: &str| {
+            let mut bytes = BASE64.decode(field).unwrap();
+            bytes[0] ^= 1;
+            BASE64.encode(bytes)
+        };
+        let tampered = [
+            Sealed {
+                ciphertext: flip(&sealed.ciphertext),
+                ..sealed.clone()
+            },
+            Sealed {
+                nonce: flip(&sealed.nonce),
+                ..sealed.clone()
+            },
+            Sealed {
+                nonce: BASE64.encode([0; 12]),
+                ..sealed.clone()
+            },
+            Sealed {
+                ciphertext: "not base64".to_string(),
+                ..sealed.clone()
+            },
+        ];
+        for sealed in &tampered {
+            assert!(cipher.open(sealed).is_err());
+        }
+    }
+
+    #[test]
+    fn test_record_format() {
+        let cipher = Cipher::new("correct horse").unwrap();
+        let line = serde_json::to_string(&cipher.seal(b"{}").unwrap()).unwrap();
+        assert!(is_sealed(&line));
+        assert!(!is_sealed(r#"{"type":"text","content":"{\"salt\":"}"#));
+        let fields = serde_json::from_str::<serde_json::Value>(&line).unwrap();
+        let fields = fields.as_object().unwrap();
+        assert_eq!(
+            fields.keys().collect::<Vec<_>>(),
+            ["ciphertext", "nonce", "salt"]
+        );
+        let salt = BASE64.decode(fields["salt"].as_str().unwrap()).unwrap();
+        let nonce = BASE64.decode(fields["nonce"].as_str().unwrap()).unwrap();
+        assert_eq!((salt.len(), nonce.len()), (SALT_LENGTH, 24));
+        // The plain text and the tag of Poly1305
+        let ciphertext = BASE64
+            .decode(fields["ciphertext"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(ciphertext.len(), 2 + 16);
+        assert!(serde_json::from_str::<Sealed>(&line.replace("{", "{\"extra\":1,")).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Tracks the selections that changed recently and decides when they have
+/// settled long enough to be read.
+///
+/// Every change pushes the deadline of its selection back by the selection's
+/// quiet window, so a burst of changes only produces a single read once the
+/// burst is over. A window of zero makes the selection due immediately.
+pub struct Debouncer<K> {
+    windows: HashMap<K, Duration>,
+    deadlines: HashMap<K, Instant>,
+}
+
+impl<K: Copy + Eq + Hash> Debouncer<K> {
+    pub fn new<I>(windows: I) -> Self
+    where
+        I: IntoIterator<Item = (K, Duration)>,
+    {
+        Debouncer {
+            windows: windows.into_iter().collect(),
+            deadlines: HashMap::new(),
+        }
+    }
+
+    /// Records a change of the selection at the given time.
+    pub fn touch(&mut self, key: K, now: Instant) {
+        let window = self.windows.get(&key).cloned().unwrap_or_default();
+        self.deadlines.insert(key, now + window);
+    }
+
+    /// Time left until the earliest pending deadline, or `None` if nothing is
+    /// pending and the caller can block until the next change.
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        self.deadlines
+            .values()
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(now))
+    }
+
+    /// Removes and returns the selections whose quiet window has passed.
+    pub fn due(&mut self, now: Instant) -> Vec<K> {
+        let due = self
+            .deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in &due {
+            self.deadlines.remove(key);
+        }
+        due
+    }
+
+    /// Removes and returns every pending selection regardless of its window,
+    /// for a last read before the watch stops.
+    pub fn flush(&mut self) -> Vec<K> {
+        self.deadlines.drain().map(|(key, _)| key).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_is_emitted_once_after_quiet_window() {
+        let start = Instant::now();
+        let window = Duration::from_millis(100);
+        let mut debouncer = Debouncer::new(vec![("clipboard", window)]);
+
+        debouncer.touch("clipboard", start);
+        debouncer.touch("clipboard", start + Duration::from_millis(60));
+        assert!(debouncer.due(start + Duration::from_millis(120)).is_empty());
+        assert_eq!(
+            debouncer.timeout(start + Duration::from_millis(120)),
+            Some(Duration::from_millis(40))
+        );
+        assert_eq!(
+            debouncer.due(start + Duration::from_millis(160)),
+            vec!["clipboard"]
+        );
+        assert_eq!(debouncer.timeout(start), None);
+    }
+
+    #[test]
+    fn test_selections_have_independent_windows() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(vec![
+            ("clipboard", Duration::from_millis(0)),
+            ("primary", Duration::from_millis(500)),
+        ]);
+        debouncer.touch("clipboard", start);
+        debouncer.touch("primary", start);
+        assert_eq!(debouncer.due(start), vec!["clipboard"]);
+        assert_eq!(debouncer.timeout(start), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_flush_returns_pending_selections() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(vec![("clipboard", Duration::from_millis(500))]);
+        debouncer.touch("clipboard", start);
+        assert!(debouncer.due(start).is_empty());
+        assert_eq!(debouncer.flush(), vec!["clipboard"]);
+        assert!(debouncer.flush().is_empty());
+        assert_eq!(debouncer.timeout(start), None);
+    }
+}
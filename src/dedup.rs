@@ -0,0 +1,54 @@
+use serde::Deserialize;
+
+/// Decides what happens when the same content is copied more than once.
+///
+/// Applications often re-assert the ownership of the clipboard or the user
+/// copies the same text twice, which would otherwise fill the history with
+/// identical entries.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupMode {
+    /// Every copy is stored as a new entry
+    Off,
+    /// A repeated copy bumps the `copy_count` and `last_copied_at` of the most
+    /// recent entry with the same content
+    #[default]
+    Merge,
+    /// A copy is dropped when it is the same as the previous entry
+    SkipConsecutive,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::ClipboardData;
+    use crate::pipeline::Sink;
+    use crate::sinks::FileSink;
+    use crate::store::read_history;
+
+    fn write_all(mode: DedupMode, contents: &[&str]) -> Vec<ClipboardData> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        let mut sink = FileSink::with_dedup(&path, mode);
+        for content in contents {
+            sink.write(&ClipboardData::new((content.to_string(), None)))
+                .unwrap();
+        }
+        read_history(&path).unwrap()
+    }
+
+    #[test]
+    fn test_merge_bumps_existing_entry() {
+        let history = write_all(DedupMode::Merge, &["a", "b", "a"]);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].meta().copy_count, 2);
+        assert!(history[0].meta().last_copied_at.is_some());
+    }
+
+    #[test]
+    fn test_skip_consecutive_only_skips_previous_entry() {
+        let history = write_all(DedupMode::SkipConsecutive, &["a", "a", "b", "a"]);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].meta().copy_count, 1);
+    }
+}
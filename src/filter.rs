@@ -0,0 +1,175 @@
+use crate::common::ClipboardData;
+use crate::pipeline::Stage;
+use chrono::{Local, NaiveTime, TimeZone, Timelike};
+use failure::{format_err, Error};
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+/// Declarative rules deciding which clipboard data gets recorded.
+///
+/// The data is recorded when it matches at least one of the `include` rules,
+/// or when there are no `include` rules at all, and none of the `exclude`
+/// rules. An allow-list is therefore written as an `include` rule and a
+/// deny-list as an `exclude` rule.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterRules {
+    pub include: Vec<Rule>,
+    pub exclude: Vec<Rule>,
+}
+
+/// A single rule which matches when every condition that is set matches.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rule {
+    /// Regular expression searched for in the content
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub content: Option<Regex>,
+    /// Minimum number of characters in the content
+    pub min_length: Option<usize>,
+    /// Maximum number of characters in the content
+    pub max_length: Option<usize>,
+    /// Case-insensitive substrings of the owner's window title
+    pub owners: Vec<String>,
+    /// Window classes (`WM_CLASS` on X11) of the owner, compared case-insensitively
+    pub owner_classes: Vec<String>,
+    /// Types of the data such as `html` or `text`
+    pub types: Vec<String>,
+    /// Local time of day range in which the data was copied
+    pub between: Option<TimeRange>,
+}
+
+impl Rule {
+    pub fn matches(&self, data: &ClipboardData) -> bool {
+        let content = data.content();
+        let length = content.chars().count();
+
+        self.content.as_ref().is_none_or(|re| re.is_match(content))
+            && self.min_length.is_none_or(|min| length >= min)
+            && self.max_length.is_none_or(|max| length <= max)
+            && (self.owners.is_empty()
+                || data.owner().is_some_and(|owner| {
+                    let owner = owner.to_lowercase();
+                    self.owners
+                        .iter()
+                        .any(|title| owner.contains(&title.to_lowercase()))
+                }))
+            && (self.owner_classes.is_empty()
+                || data.meta().owner_class.as_ref().is_some_and(|class| {
+                    self.owner_classes
+                        .iter()
+                        .any(|name| name.eq_ignore_ascii_case(class))
+                }))
+            && (self.types.is_empty() || self.types.iter().any(|name| name == data.type_name()))
+            && self.between.is_none_or(|range| {
+                Local
+                    .timestamp_opt(data.created_at() as i64, 0)
+                    .single()
+                    .is_some_and(|time| range.contains(time.time()))
+            })
+    }
+}
+
+impl FilterRules {
+    /// Checks whether the data should be recorded.
+    pub fn allows(&self, data: &ClipboardData) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|rule| rule.matches(data)))
+            && !self.exclude.iter().any(|rule| rule.matches(data))
+    }
+}
+
+impl Stage for FilterRules {
+    fn process(&mut self, data: ClipboardData) -> Result<Option<ClipboardData>, Error> {
+        Ok(if self.allows(&data) { Some(data) } else { None })
+    }
+}
+
+/// A range of the day written as `HH:MM-HH:MM`. The range wraps around
+/// midnight when the end is before the start, e.g. `22:00-06:00`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeRange {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeRange {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        // Seconds are ignored so that `09:00-17:00` includes 17:00:30
+        let time = time.with_second(0).unwrap_or(time);
+        if self.start <= self.end {
+            self.start <= time && time <= self.end
+        } else {
+            time >= self.start || time <= self.end
+        }
+    }
+}
+
+impl std::str::FromStr for TimeRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut parts = s.splitn(2, '-');
+        let mut parse = || {
+            let part = parts.next().unwrap_or_default().trim();
+            NaiveTime::parse_from_str(part, "%H:%M")
+                .map_err(|_| format_err!("Invalid time range '{}', expected HH:MM-HH:MM", s))
+        };
+        Ok(TimeRange {
+            start: parse()?,
+            end: parse()?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pattern: Option<String> = Option::deserialize(deserializer)?;
+    pattern
+        .map(|pattern| Regex::new(&pattern).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(content: &str, owner: &str) -> ClipboardData {
+        ClipboardData::new((content.to_string(), Some(owner.to_string())))
+    }
+
+    #[test]
+    fn test_include_and_exclude_rules() {
+        let rules: FilterRules = serde_json::from_str(
+            r#"{
+                "include": [{ "owners": ["firefox"] }, { "types": ["html"] }],
+                "exclude": [{ "content": "^\\d{6}$" }, { "max_length": 1 }]
+            }"#,
+        )
+        .unwrap();
+
+        assert!(rules.allows(&text("hello", "Mozilla Firefox")));
+        assert!(!rules.allows(&text("hello", "KeePassXC")));
+        assert!(!rules.allows(&text("123456", "Mozilla Firefox")));
+        assert!(!rules.allows(&text("a", "Mozilla Firefox")));
+        assert!(rules.allows(&ClipboardData::new(("<b>hi</b>".to_string(), None, None))));
+    }
+
+    #[test]
+    fn test_time_range_wraps_midnight() {
+        let night: TimeRange = "22:00-06:00".parse().unwrap();
+        assert!(night.contains(NaiveTime::from_hms_opt(23, 30, 0).unwrap()));
+        assert!(night.contains(NaiveTime::from_hms_opt(6, 0, 45).unwrap()));
+        assert!(!night.contains(NaiveTime::from_hms_opt(12, 0, 0).unwrap()));
+        assert!("9-5".parse::<TimeRange>().is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::common::{ClipboardData, ClipboardFunctions, ClipboardSink, ClipboardTargets};
+use failure::{format_err, Error};
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// A clipboard that does not need a display server.
+///
+/// Every line read from the input is treated as a new clipboard selection,
+/// which makes it possible to run the rest of the program on a server or in
+/// tests by piping text into it. The input defaults to the standard input.
+pub struct HeadlessClipboard {
+    /// Source of the simulated clipboard changes
+    input: RefCell<Box<dyn BufRead>>,
+    /// Text of the last selection that was read from the input
+    current: RefCell<Option<String>>,
+}
+
+impl HeadlessClipboard {
+    /// Creates a headless clipboard that reads its selections from stdin.
+    pub fn new() -> Self {
+        HeadlessClipboard::from_reader(io::stdin())
+    }
+
+    /// Creates a headless clipboard that reads its selections from `reader`.
+    pub fn from_reader<R: Read + 'static>(reader: R) -> Self {
+        HeadlessClipboard {
+            input: RefCell::new(Box::new(BufReader::new(reader))),
+            current: RefCell::new(None),
+        }
+    }
+
+    /// Reads the next selection from the input, returning `None` once the
+    /// input is exhausted.
+    fn next_selection(&self) -> Result<Option<String>, Error> {
+        let mut line = String::new();
+        if self.input.borrow_mut().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end_matches(&['\r', '\n'][..]).to_string();
+        *self.current.borrow_mut() = Some(line.clone());
+        Ok(Some(line))
+    }
+}
+
+impl Default for HeadlessClipboard {
+    fn default() -> Self {
+        HeadlessClipboard::new()
+    }
+}
+
+impl ClipboardFunctions for HeadlessClipboard {
+    /// The headless clipboard only ever holds plain text.
+    fn get_targets(&self) -> Result<ClipboardTargets, Error> {
+        let targets = match *self.current.borrow() {
+            Some(_) => vec!["text/plain".to_string()],
+            None => vec![],
+        };
+        Ok(ClipboardTargets::MIME(targets))
+    }
+
+    /// Returns the last selection read from the input.
+    fn get_clipboard(&self) -> Result<ClipboardData, Error> {
+        let content = self
+            .current
+            .borrow()
+            .clone()
+            .ok_or_else(|| format_err!("The clipboard is empty"))?;
+        Ok(ClipboardData::new((content, None)))
+    }
+
+    /// Calls the callback for every line of the input and returns when the
+    /// input is closed.
+    fn watch_clipboard(&self, callback: &ClipboardSink) {
+        loop {
+            match self.next_selection() {
+                Ok(Some(_)) => {
+                    let clipboard_data = ClipboardFunctions::get_clipboard(self);
+                    match clipboard_data {
+                        Ok(data) => {
+                            if let Err(e) = callback.0(data) {
+                                eprintln!("An error has occured in the callback function {}", e);
+                            }
+                        }
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Could not read from the input {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,675 @@
+use crate::table::Table;
+use ego_tree::NodeRef;
+use scraper::{Html, Node};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// A link found in an HTML fragment.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Link {
+    pub href: String,
+    /// Text of the anchor, or the alt text of the image inside of it
+    pub text: String,
+}
+
+/// An image referenced by an HTML fragment.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Image {
+    pub src: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+/// Renders an HTML fragment as plain text, with paragraphs separated by blank
+/// lines, list items on their own lines and table cells separated by tabs.
+pub fn to_text(html: &str) -> String {
+    Converter { markdown: false }.convert(html)
+}
+
+/// Converts an HTML fragment to Markdown, using GitHub flavoured tables.
+pub fn to_markdown(html: &str) -> String {
+    Converter { markdown: true }.convert(html)
+}
+
+/// Extracts the links and images of an HTML fragment. Relative URLs are
+/// resolved against the base URL when there is one, and links to scripts are
+/// left out.
+pub fn extract_resources(html: &str, base: Option<&str>) -> (Vec<Link>, Vec<Image>) {
+    let base = base.and_then(|base| Url::parse(base).ok());
+    let resolve = |url: &str| {
+        let url = url.trim();
+        base.as_ref()
+            .map_or_else(|| Url::parse(url), |base| base.join(url))
+            .map(String::from)
+            .unwrap_or_else(|_| url.to_string())
+    };
+
+    let document = Html::parse_fragment(html);
+    let mut links = vec![];
+    let mut images = vec![];
+    for node in document.root_element().descendants() {
+        let element = match node.value().as_element() {
+            Some(element) => element,
+            None => continue,
+        };
+        match (element.name(), element.attr("href"), element.attr("src")) {
+            ("a", Some(href), _) => {
+                if href.trim().is_empty() || href.trim_start().starts_with("javascript:") {
+                    continue;
+                }
+                let mut text = String::new();
+                for descendant in node.descendants() {
+                    match descendant.value() {
+                        Node::Text(part) => text.push_str(part),
+                        Node::Element(image) if image.name() == "img" => {
+                            text.push_str(image.attr("alt").unwrap_or_default())
+                        }
+                        _ => {}
+                    }
+                }
+                links.push(Link {
+                    href: resolve(href),
+                    text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+                });
+            }
+            ("img", _, Some(src)) if !src.trim().is_empty() => images.push(Image {
+                src: resolve(src),
+                alt: element.attr("alt").map(String::from),
+                width: dimension(element.attr("width"), element.attr("style"), "width"),
+                height: dimension(element.attr("height"), element.attr("style"), "height"),
+            }),
+            _ => {}
+        }
+    }
+    (links, images)
+}
+
+/// Extracts the first table of an HTML fragment. The first row is the header
+/// when it is in a `<thead>` or made of `<th>` cells, and is guessed otherwise.
+pub fn extract_table(html: &str) -> Option<Table> {
+    let document = Html::parse_fragment(html);
+    let is = |node: &NodeRef<Node>, name: &str| {
+        node.value()
+            .as_element()
+            .is_some_and(|element| element.name() == name)
+    };
+    let table = document
+        .root_element()
+        .descendants()
+        .find(|node| is(node, "table"))?;
+    let rows = Converter { markdown: false }.table_rows(table);
+    let first_row = table.descendants().find(|node| is(node, "tr"))?;
+    let header = first_row
+        .parent()
+        .is_some_and(|parent| is(&parent, "thead"))
+        || first_row
+            .children()
+            .filter(|cell| cell.value().is_element())
+            .all(|cell| is(&cell, "th"));
+    Table::from_rows(rows, if header { Some(true) } else { None })
+}
+
+/// Size in pixels from the attribute, or else from the inline style.
+fn dimension(attribute: Option<&str>, style: Option<&str>, property: &str) -> Option<u32> {
+    let from_style = || {
+        style?
+            .split(';')
+            .filter_map(|declaration| declaration.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case(property))
+            .map(|(_, value)| value)
+    };
+    attribute
+        .or_else(from_style)
+        .and_then(|value| value.trim().trim_end_matches("px").trim().parse().ok())
+}
+
+/// Block level structure of the document. The inline content of the blocks is
+/// already rendered, with `\n` marking hard line breaks.
+#[derive(Debug)]
+enum Block {
+    Paragraph(String),
+    Heading(usize, String),
+    Code(Option<String>, String),
+    List {
+        ordered: bool,
+        items: Vec<Vec<Block>>,
+    },
+    Quote(Vec<Block>),
+    Table(Vec<Vec<String>>),
+    Rule,
+}
+
+/// Collects the blocks of a container along with the inline content of the
+/// paragraph that is currently open.
+#[derive(Default)]
+struct Builder {
+    blocks: Vec<Block>,
+    inline: String,
+    /// Inside the bullet of a list paragraph copied from Word
+    in_word_bullet: bool,
+    word_bullet: String,
+}
+
+impl Builder {
+    /// Appends text, collapsing runs of whitespace into a single space.
+    fn push_text(&mut self, text: &str) {
+        for c in text.chars() {
+            if c.is_whitespace() {
+                if !self.inline.ends_with(' ') && !self.inline.ends_with('\n') {
+                    self.inline.push(' ');
+                }
+            } else {
+                self.inline.push(c);
+            }
+        }
+    }
+
+    /// Appends already collapsed inline content.
+    fn push_inline(&mut self, inline: &str) {
+        let inline = if self.inline.is_empty() || self.inline.ends_with(' ') {
+            inline.trim_start_matches(' ')
+        } else {
+            inline
+        };
+        self.inline.push_str(inline);
+    }
+
+    /// Ends the open paragraph.
+    fn flush(&mut self) {
+        let paragraph = clean_lines(&self.inline);
+        self.inline.clear();
+        if !paragraph.is_empty() {
+            self.blocks.push(Block::Paragraph(paragraph));
+        }
+    }
+
+    fn push_block(&mut self, block: Block) {
+        self.flush();
+        self.blocks.push(block);
+    }
+}
+
+/// Trims every line and drops the empty ones at the start and the end.
+fn clean_lines(text: &str) -> String {
+    text.split('\n')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_matches('\n')
+        .to_string()
+}
+
+struct Converter {
+    markdown: bool,
+}
+
+impl Converter {
+    fn convert(&self, html: &str) -> String {
+        let document = Html::parse_fragment(html);
+        let blocks = self.blocks(*document.root_element());
+        self.render(&blocks, "\n\n")
+    }
+
+    /// Builds the blocks making up the children of the node.
+    fn blocks(&self, node: NodeRef<Node>) -> Vec<Block> {
+        let mut builder = Builder::default();
+        self.walk_children(node, &mut builder);
+        builder.flush();
+        builder.blocks
+    }
+
+    /// Renders the children of the node as inline content. Blocks nested in
+    /// inline elements, like a `<div>` inside a link, are joined with spaces.
+    fn inline(&self, node: NodeRef<Node>) -> String {
+        let mut builder = Builder::default();
+        self.walk_children(node, &mut builder);
+        if builder.blocks.is_empty() {
+            return builder.inline;
+        }
+        builder.flush();
+        builder
+            .blocks
+            .iter()
+            .map(|block| self.render_block(block))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn walk_children(&self, node: NodeRef<Node>, builder: &mut Builder) {
+        let mut word_list: Option<(bool, Vec<Vec<Block>>)> = None;
+        for child in node.children() {
+            // Word writes lists as paragraphs with an `mso-list` style, which
+            // are gathered back into a list
+            let word_item = child.value().as_element().is_some_and(|element| {
+                element.name() == "p"
+                    && element
+                        .attr("style")
+                        .is_some_and(|style| style.contains("mso-list"))
+            });
+            if word_item {
+                let mut item = Builder::default();
+                self.walk_children(child, &mut item);
+                item.flush();
+                let ordered = word_list.as_ref().map_or_else(
+                    || {
+                        let bullet = item.word_bullet.trim();
+                        bullet.starts_with(|c: char| c.is_ascii_alphanumeric())
+                            && bullet.ends_with(['.', ')'])
+                    },
+                    |(ordered, _)| *ordered,
+                );
+                word_list
+                    .get_or_insert_with(|| (ordered, vec![]))
+                    .1
+                    .push(item.blocks);
+                continue;
+            }
+            let blank = child
+                .value()
+                .as_text()
+                .is_some_and(|text| text.trim().is_empty());
+            if blank && word_list.is_some() {
+                continue;
+            }
+            if let Some((ordered, items)) = word_list.take() {
+                builder.push_block(Block::List { ordered, items });
+            }
+            self.walk(child, builder);
+        }
+        if let Some((ordered, items)) = word_list {
+            builder.push_block(Block::List { ordered, items });
+        }
+    }
+
+    fn walk(&self, node: NodeRef<Node>, builder: &mut Builder) {
+        let element = match node.value() {
+            Node::Text(text) => {
+                if builder.in_word_bullet {
+                    builder.word_bullet.push_str(text);
+                } else {
+                    builder.push_text(text);
+                }
+                return;
+            }
+            Node::Comment(comment) => {
+                match comment.trim() {
+                    "[if !supportLists]" => builder.in_word_bullet = true,
+                    "[endif]" => builder.in_word_bullet = false,
+                    _ => {}
+                }
+                return;
+            }
+            Node::Element(element) => element,
+            _ => return,
+        };
+        if builder.in_word_bullet {
+            self.walk_children(node, builder);
+            return;
+        }
+
+        match element.name() {
+            "script" | "style" | "head" | "title" | "meta" | "link" | "noscript" | "template" => {}
+            "br" => builder.inline.push('\n'),
+            "hr" => builder.push_block(Block::Rule),
+            name @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+                let level = usize::from(name.as_bytes()[1] - b'0');
+                let heading = clean_lines(&self.inline(node)).replace('\n', " ");
+                if !heading.is_empty() {
+                    builder.push_block(Block::Heading(level, heading));
+                }
+            }
+            "pre" => {
+                let language = code_language(node);
+                let code = collect_text(node);
+                let code = code.strip_prefix('\n').unwrap_or(&code).trim_end();
+                builder.push_block(Block::Code(language, code.to_string()));
+            }
+            name @ ("ul" | "ol") => {
+                let mut items: Vec<Vec<Block>> = vec![];
+                for child in node.children() {
+                    match child.value().as_element().map(|element| element.name()) {
+                        Some("li") => items.push(self.blocks(child)),
+                        // Lists nested directly in a list belong to the item before
+                        Some("ul") | Some("ol") => {
+                            let mut nested = Builder::default();
+                            self.walk(child, &mut nested);
+                            match items.last_mut() {
+                                Some(item) => item.extend(nested.blocks),
+                                None => items.push(nested.blocks),
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                let ordered = name == "ol";
+                builder.push_block(Block::List { ordered, items });
+            }
+            "blockquote" => {
+                let blocks = self.blocks(node);
+                builder.push_block(Block::Quote(blocks));
+            }
+            "table" => {
+                let rows = self.table_rows(node);
+                if !rows.is_empty() {
+                    builder.push_block(Block::Table(rows));
+                }
+            }
+            "p" | "div" | "section" | "article" | "header" | "footer" | "main" | "nav"
+            | "aside" | "figure" | "figcaption" | "address" | "dl" | "dt" | "dd" | "li"
+            | "center" | "form" | "fieldset" | "details" | "summary" | "caption" => {
+                builder.flush();
+                self.walk_children(node, builder);
+                builder.flush();
+            }
+            "img" if self.markdown => {
+                if let Some(src) = element.attr("src") {
+                    let alt = element.attr("alt").unwrap_or_default();
+                    builder.push_inline(&format!("![{}]({})", alt, src));
+                }
+            }
+            "a" => {
+                let text = self.inline(node);
+                match element.attr("href") {
+                    Some(href)
+                        if self.markdown
+                            && !text.trim().is_empty()
+                            && !href.starts_with("javascript:") =>
+                    {
+                        builder.push_inline(&wrap(&text, "[", &format!("]({})", href)));
+                    }
+                    _ => builder.push_inline(&text),
+                }
+            }
+            "code" | "kbd" | "samp" | "tt" if self.markdown => {
+                let code = collect_text(node);
+                if !code.is_empty() {
+                    let fence = if code.contains('`') { "``" } else { "`" };
+                    builder.push_inline(&format!("{}{}{}", fence, code, fence));
+                }
+            }
+            name => {
+                let marker = match name {
+                    // Google Docs wraps the whole copy in a `<b>` that is not bold
+                    "b" if element
+                        .id()
+                        .is_some_and(|id| id.starts_with("docs-internal-guid")) =>
+                    {
+                        None
+                    }
+                    "strong" | "b" => Some("**"),
+                    "em" | "i" | "cite" => Some("*"),
+                    "del" | "s" | "strike" => Some("~~"),
+                    _ => element.attr("style").and_then(style_marker),
+                };
+                match marker {
+                    Some(marker) if self.markdown => {
+                        let text = self.inline(node);
+                        builder.push_inline(&wrap(&text, marker, marker));
+                    }
+                    _ => self.walk_children(node, builder),
+                }
+            }
+        }
+    }
+
+    /// Rows of the table with their cells rendered as inline content. Rows of
+    /// tables nested in a cell are not included.
+    fn table_rows(&self, table: NodeRef<Node>) -> Vec<Vec<String>> {
+        let mut rows = vec![];
+        for child in table.children() {
+            match child.value().as_element().map(|element| element.name()) {
+                Some("thead") | Some("tbody") | Some("tfoot") => {
+                    rows.extend(self.table_rows(child))
+                }
+                Some("tr") => {
+                    let cells = child
+                        .children()
+                        .filter(|cell| {
+                            cell.value()
+                                .as_element()
+                                .is_some_and(|element| matches!(element.name(), "td" | "th"))
+                        })
+                        .map(|cell| clean_lines(&self.inline(cell)).replace('\n', " "))
+                        .collect::<Vec<_>>();
+                    rows.push(cells);
+                }
+                _ => {}
+            }
+        }
+        rows
+    }
+
+    fn render(&self, blocks: &[Block], separator: &str) -> String {
+        blocks
+            .iter()
+            .map(|block| self.render_block(block))
+            .filter(|block| !block.is_empty())
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    fn render_block(&self, block: &Block) -> String {
+        match block {
+            Block::Paragraph(text) if self.markdown => text.replace('\n', "\\\n"),
+            Block::Paragraph(text) => text.clone(),
+            Block::Heading(level, text) if self.markdown => {
+                format!("{} {}", "#".repeat(*level), text)
+            }
+            Block::Heading(_, text) => text.clone(),
+            Block::Code(language, code) if self.markdown => format!(
+                "```{}\n{}\n```",
+                language.as_deref().unwrap_or_default(),
+                code
+            ),
+            Block::Code(_, code) => code.clone(),
+            Block::List { ordered, items } => items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let marker = match (ordered, self.markdown) {
+                        (true, _) => format!("{}. ", i + 1),
+                        (false, true) => "- ".to_string(),
+                        (false, false) => "• ".to_string(),
+                    };
+                    let indent = " ".repeat(marker.chars().count());
+                    let content = self.render(item, "\n");
+                    let content = content
+                        .lines()
+                        .enumerate()
+                        .map(|(j, line)| match j {
+                            0 => format!("{}{}", marker, line),
+                            _ if line.is_empty() => String::new(),
+                            _ => format!("{}{}", indent, line),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    if content.is_empty() {
+                        marker.trim_end().to_string()
+                    } else {
+                        content
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Block::Quote(blocks) if self.markdown => self
+                .render(blocks, "\n\n")
+                .lines()
+                .map(|line| format!("> {}", line).trim_end().to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Block::Quote(blocks) => self.render(blocks, "\n\n"),
+            Block::Table(rows) if self.markdown => {
+                let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+                let row = |cells: &[String]| {
+                    let cells = (0..columns)
+                        .map(|i| cells.get(i).map_or("", String::as_str).replace('|', "\\|"))
+                        .collect::<Vec<_>>();
+                    format!("| {} |", cells.join(" | "))
+                };
+                let mut lines = vec![row(&rows[0]), row(&vec!["---".to_string(); columns])];
+                lines.extend(rows[1..].iter().map(|cells| row(cells)));
+                lines.join("\n")
+            }
+            Block::Table(rows) => rows
+                .iter()
+                .map(|cells| cells.join("\t"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Block::Rule if self.markdown => "---".to_string(),
+            Block::Rule => String::new(),
+        }
+    }
+}
+
+/// Puts the markers around the text while keeping the surrounding spaces
+/// outside of them, as `** bold **` is not bold in Markdown.
+fn wrap(text: &str, open: &str, close: &str) -> String {
+    let trimmed = text.trim_matches(' ');
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    let lead = if text.starts_with(' ') { " " } else { "" };
+    let trail = if text.ends_with(' ') { " " } else { "" };
+    format!("{}{}{}{}{}", lead, open, trimmed, close, trail)
+}
+
+/// Emphasis expressed with inline styles, as office suites do.
+fn style_marker(style: &str) -> Option<&'static str> {
+    let style = style.replace(' ', "").to_lowercase();
+    if style.contains("font-weight:700") || style.contains("font-weight:bold") {
+        Some("**")
+    } else if style.contains("font-style:italic") {
+        Some("*")
+    } else {
+        None
+    }
+}
+
+/// Language of a code block from a `language-*` or `lang-*` class on the
+/// `<pre>` or the `<code>` inside it.
+fn code_language(pre: NodeRef<Node>) -> Option<String> {
+    let code = pre.children().find(|child| {
+        child
+            .value()
+            .as_element()
+            .is_some_and(|e| e.name() == "code")
+    });
+    std::iter::once(pre)
+        .chain(code)
+        .filter_map(|node| node.value().as_element())
+        .flat_map(|element| element.classes())
+        .find_map(|class| {
+            class
+                .strip_prefix("language-")
+                .or_else(|| class.strip_prefix("lang-"))
+                .map(String::from)
+        })
+}
+
+/// Text of the node and its descendants with the whitespace kept as is.
+fn collect_text(node: NodeRef<Node>) -> String {
+    node.descendants()
+        .filter_map(|node| match node.value() {
+            Node::Text(text) => Some(&**text),
+            Node::Element(element) if element.name() == "br" => Some("\n"),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    macro_rules! fixture {
+        ($name:expr) => {
+            (
+                include_str!(concat!("../tests/fixtures/html/", $name, ".html")),
+                include_str!(concat!("../tests/fixtures/html/", $name, ".txt")),
+                include_str!(concat!("../tests/fixtures/html/", $name, ".md")),
+            )
+        };
+    }
+
+    #[test]
+    fn test_fixtures() {
+        let fixtures = vec![
+            ("chrome", fixture!("chrome")),
+            ("firefox", fixture!("firefox")),
+            ("google_docs", fixture!("google_docs")),
+            ("word", fixture!("word")),
+            ("libreoffice", fixture!("libreoffice")),
+        ];
+        for (name, (html, text, markdown)) in fixtures {
+            assert_eq!(to_text(html), text.trim_end(), "text of {}", name);
+            assert_eq!(
+                to_markdown(html),
+                markdown.trim_end(),
+                "markdown of {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_extract_resources() {
+        let html = concat!(
+            r#"<p><a href="/docs/intro">Read   the <b>docs</b></a> or "#,
+            r#"<a href="javascript:void(0)">nothing</a>.</p>"#,
+            r#"<a href="https://example.org/"><img src="logo.png" alt="Example" width="64" "#,
+            r#"style="height: 32px"></a>"#
+        );
+        let (links, images) = extract_resources(html, Some("https://example.com/blog/post"));
+        assert_eq!(
+            links,
+            vec![
+                Link {
+                    href: "https://example.com/docs/intro".to_string(),
+                    text: "Read the docs".to_string(),
+                },
+                Link {
+                    href: "https://example.org/".to_string(),
+                    text: "Example".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            images,
+            vec![Image {
+                src: "https://example.com/blog/logo.png".to_string(),
+                alt: Some("Example".to_string()),
+                width: Some(64),
+                height: Some(32),
+            }]
+        );
+        assert_eq!(extract_resources(html, None).1[0].src, "logo.png");
+    }
+
+    #[test]
+    fn test_extract_table() {
+        let (html, _, _) = fixture!("firefox");
+        let table = extract_table(html).unwrap();
+        assert_eq!(table.headers, vec!["Name", "Stars"]);
+        assert_eq!(table.rows[0], vec![json!("serde"), json!("9 000")]);
+
+        let (html, _, _) = fixture!("libreoffice");
+        let table = extract_table(html).unwrap();
+        assert_eq!(table.headers, vec!["Item", "Qty"]);
+        assert_eq!(table.rows[1], vec![json!("Pears"), json!(12)]);
+    }
+
+    #[test]
+    fn test_inline_whitespace() {
+        assert_eq!(to_markdown("a<b> bold </b>c"), "a **bold** c");
+        assert_eq!(to_text("  one\n  <br> two  "), "one\ntwo");
+        assert_eq!(
+            to_markdown("<ul><li>a<ul><li>b</li></ul></li></ul>"),
+            "- a\n  - b"
+        );
+    }
+}
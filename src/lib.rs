@@ -1,18 +1,72 @@
+mod backend;
+mod blobs;
+mod classify;
 mod common;
-pub use common::{ClipboardData, ClipboardFunctions, ClipboardSink};
+mod config;
+mod crypto;
+#[cfg(target_os = "linux")]
+mod debounce;
+mod dedup;
+mod filter;
+pub mod headless_clipboard;
+mod html;
+mod logs;
+mod maildir;
+mod pipeline;
+mod redact;
+mod retention;
+mod sanitize;
+mod segments;
+mod sinks;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod store;
+mod structured;
+mod table;
+mod terminal;
+mod trace;
+mod urls;
+pub use backend::{backends, Backend, BackendKind, Clipboard};
+pub use blobs::{BlobRef, BlobStore};
+pub use classify::{classify, Kind, Stats};
+pub use common::{
+    hash_content, ClipboardData, ClipboardFunctions, ClipboardSink, ClipboardTargets,
+    ClipboardWriter, Metadata, Selection, SelectionOptions, WatchOptions,
+};
+pub use config::{Config, PipelineConfig, SinkConfig, StageConfig};
+pub use crypto::{Cipher, NEW_PASSPHRASE_VAR, PASSPHRASE_VAR};
+pub use dedup::DedupMode;
+pub use filter::{FilterRules, Rule, TimeRange};
+pub use html::{Image, Link};
+pub use logs::{parse_log, LogEntry};
+pub use maildir::{read_maildir, MaildirSink};
+pub use pipeline::{Pipeline, Sink, Stage};
+pub use redact::{luhn_check, shannon_entropy, Detector, Finding, RedactAction, Redactor};
+pub use retention::{parse_duration, Pruned, Retention};
+pub use sanitize::Sanitizer;
+pub use segments::{list_segments, Compression, Rollover};
+pub use sinks::{FileSink, HttpSink, StdoutSink};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteSink;
+pub use store::{
+    append_record, migrate, prune, read_encrypted_history, read_history, recover, rekey,
+    DamagedRecord, HistoryIndex, HistoryLock, HistoryReader, Recovery,
+};
+pub use structured::{parse_document, Format};
+pub use table::Table;
+pub use terminal::TerminalCleanup;
+pub use trace::{parse_stack_trace, Frame, StackTrace};
+pub use urls::{clean_url, is_tracking_parameter, UrlCleaner, UrlParts};
 
 #[cfg(target_os = "linux")]
 #[path = ""]
 pub mod clipboard {
+    pub mod wayland_clipboard;
     pub mod x11_clipboard;
-    pub type Clipboard = x11_clipboard::ClipboardOwner;
 }
 
 #[cfg(windows)]
 #[path = ""]
 pub mod clipboard {
     pub mod winapi_clipboard;
-    pub type Clipboard = winapi_clipboard::ClipboardOwner;
 }
-
-pub use crate::clipboard::Clipboard;
@@ -0,0 +1,173 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// A line of a log excerpt.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    /// Level in lowercase, e.g. `info` or `error`
+    pub level: String,
+    /// Module, logger or process that wrote the line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub message: String,
+    /// `key=value` pairs and the other keys of JSON logs
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub fields: Map<String, Value>,
+}
+
+const LEVELS: &[&str] = &[
+    "trace", "debug", "info", "notice", "warn", "warning", "error", "err", "fatal", "critical",
+    "crit",
+];
+
+lazy_static! {
+    /// Timestamp, level and target in the orders used by env_logger, tracing,
+    /// log4j, Python's logging and most other text loggers
+    static ref TEXT_LINE: Regex = Regex::new(concat!(
+        r"^\[?(?P<timestamp>\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?",
+        r"|[A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2})?\]?\s*",
+        r"[\[<]?(?P<level>(?i:trace|debug|info|notice|warn|warning|error|err|fatal|critical|crit))[\]>]?\s+",
+        r"(?:\[(?P<thread>[^\]]+)\]\s+)?",
+        r"(?:(?P<target>[\w:.$-]+)(?:\]|:| -)\s+)?",
+        r"(?P<message>.*)$"
+    ))
+    .unwrap();
+    static ref FIELD: Regex =
+        Regex::new(r#"(?:^|\s)([\w.-]+)=("(?:[^"\\]|\\.)*"|\S+)"#).unwrap();
+}
+
+/// Parses the text as a log excerpt. Every line has to be a log line, except
+/// for indented lines or lines without a level which continue the message of
+/// the previous one, such as the stack trace of an error.
+pub fn parse_log(text: &str) -> Vec<LogEntry> {
+    let mut entries: Vec<LogEntry> = vec![];
+    for line in text
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+    {
+        match parse_line(line) {
+            Some(entry) => entries.push(entry),
+            None => match entries.last_mut() {
+                Some(entry) => {
+                    entry.message.push('\n');
+                    entry.message.push_str(line);
+                }
+                None => return vec![],
+            },
+        }
+    }
+    entries
+}
+
+fn parse_line(line: &str) -> Option<LogEntry> {
+    if line.starts_with('{') {
+        return serde_json::from_str(line).ok().and_then(from_json);
+    }
+    let fields = parse_fields(line);
+    if fields.contains_key("level") && fields.len() >= 2 {
+        return from_json(fields);
+    }
+
+    let captures = TEXT_LINE.captures(line)?;
+    // Prose can start with a word like "Error" too
+    let level = &captures["level"];
+    if captures.name("timestamp").is_none()
+        && !line.starts_with(['[', '<'])
+        && level != level.to_uppercase()
+    {
+        return None;
+    }
+    let message = captures["message"].to_string();
+    let mut fields = parse_fields(&message);
+    if let Some(thread) = captures.name("thread") {
+        fields.insert("thread".to_string(), Value::from(thread.as_str()));
+    }
+    Some(LogEntry {
+        timestamp: captures.name("timestamp").map(|t| t.as_str().to_string()),
+        level: level.to_lowercase(),
+        target: captures.name("target").map(|t| t.as_str().to_string()),
+        message,
+        fields,
+    })
+}
+
+/// Builds an entry out of a JSON log or logfmt fields using the usual names
+/// of the level, time and message keys.
+fn from_json(mut fields: Map<String, Value>) -> Option<LogEntry> {
+    let mut take = |keys: &[&str]| {
+        keys.iter().find_map(|key| {
+            fields.remove(*key).map(|value| match value {
+                Value::String(string) => string,
+                value => value.to_string(),
+            })
+        })
+    };
+    let level = take(&["level", "lvl", "severity", "levelname"])?.to_lowercase();
+    if !LEVELS.contains(&level.as_str()) {
+        return None;
+    }
+    Some(LogEntry {
+        timestamp: take(&["time", "timestamp", "ts", "@timestamp", "asctime"]),
+        target: take(&["target", "logger", "name", "module"]),
+        message: take(&["msg", "message"]).unwrap_or_default(),
+        level,
+        fields,
+    })
+}
+
+/// Collects the `key=value` pairs of a line, unquoting quoted values.
+fn parse_fields(text: &str) -> Map<String, Value> {
+    FIELD
+        .captures_iter(text)
+        .map(|field| {
+            let value = &field[2];
+            let value = serde_json::from_str::<String>(value)
+                .ok()
+                .unwrap_or_else(|| value.to_string());
+            (field[1].to_string(), Value::String(value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_text_logs() {
+        let log = "2024-01-15 10:23:45,123 ERROR [main] com.example.Db - query failed table=users\n\tat com.example.Db.run(Db.java:10)\n[2024-01-15T10:23:46Z INFO  my_app::server] listening port=8080";
+        let entries = parse_log(log);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].level, "error");
+        assert_eq!(
+            entries[0].timestamp.as_deref(),
+            Some("2024-01-15 10:23:45,123")
+        );
+        assert_eq!(entries[0].target.as_deref(), Some("com.example.Db"));
+        assert!(entries[0].message.ends_with("(Db.java:10)"));
+        assert_eq!(entries[0].fields["thread"], json!("main"));
+        assert_eq!(entries[0].fields["table"], json!("users"));
+        assert_eq!(entries[1].target.as_deref(), Some("my_app::server"));
+        assert_eq!(entries[1].message, "listening port=8080");
+    }
+
+    #[test]
+    fn test_structured_logs() {
+        let log = "time=2024-01-15T10:23:45Z level=warn msg=\"disk almost full\" used=91%\n{\"level\":\"INFO\",\"ts\":1705314225.1,\"msg\":\"ok\",\"id\":7}";
+        let entries = parse_log(log);
+        assert_eq!(entries[0].message, "disk almost full");
+        assert_eq!(entries[0].fields["used"], json!("91%"));
+        assert_eq!(entries[1].level, "info");
+        assert_eq!(entries[1].timestamp.as_deref(), Some("1705314225.1"));
+        assert_eq!(entries[1].fields["id"], json!(7));
+
+        assert!(parse_log("Info: the office is closed\non Friday").is_empty());
+        assert!(parse_log("Error messages are shown in red").is_empty());
+    }
+}
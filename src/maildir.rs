@@ -0,0 +1,171 @@
+use crate::common::ClipboardData;
+use crate::pipeline::Sink;
+use crate::segments::sync_dir;
+use crate::store::{create_private_dir, private_options};
+use failure::{format_err, Error};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of the next delivery of this process
+static DELIVERIES: AtomicU64 = AtomicU64::new(0);
+
+/// Stores every record in a file of its own, in a directory laid out like a
+/// Maildir, so that other tools can use the history without parsing it.
+///
+/// Each record is delivered to `new/` as
+/// `<created_at>.M<microseconds>P<pid>Q<delivery>-<content_hash>.json` next
+/// to a file with the raw content, e.g. `.txt` or `.html`. Like in a Maildir,
+/// the time of the delivery, the process id and a counter make the name
+/// unique, so that the same content copied twice within a second is never
+/// delivered over the earlier files. Files are written to `tmp/` first and
+/// then renamed, so `new/` never holds a partial file. Records are read from
+/// `cur/` as well, where other tools may move them.
+pub struct MaildirSink {
+    dir: PathBuf,
+}
+
+impl MaildirSink {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        MaildirSink { dir: dir.into() }
+    }
+
+    /// Writes the bytes to `tmp/` and renames the file to `new/`.
+    fn deliver(&self, name: &str, bytes: &[u8]) -> Result<PathBuf, Error> {
+        let temp = self.dir.join("tmp").join(name);
+        let mut file = private_options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        let path = self.dir.join("new").join(name);
+        fs::rename(&temp, &path)?;
+        sync_dir(&path)?;
+        Ok(path)
+    }
+}
+
+impl Sink for MaildirSink {
+    fn name(&self) -> String {
+        format!("maildir {}", self.dir.display())
+    }
+
+    /// Delivers the raw content first and the record last, so that a record
+    /// in `new/` always has its content next to it.
+    fn write(&mut self, data: &ClipboardData) -> Result<(), Error> {
+        for sub in &["tmp", "new", "cur"] {
+            create_private_dir(&self.dir.join(sub))?;
+        }
+        let mut data = data.clone();
+        if data.meta().content_hash.is_empty() {
+            data.refresh_hash();
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let name = format!(
+            "{}.M{:06}P{}Q{}-{}",
+            data.created_at(),
+            now.subsec_micros(),
+            process::id(),
+            DELIVERIES.fetch_add(1, Ordering::Relaxed),
+            data.meta().content_hash
+        );
+        let extension = match data {
+            ClipboardData::Html { .. } => "html",
+            ClipboardData::UnicodeText { .. } => "txt",
+        };
+        self.deliver(
+            &format!("{}.{}", name, extension),
+            data.content().as_bytes(),
+        )?;
+        let mut record = serde_json::to_vec_pretty(&data)?;
+        record.push(b'\n');
+        self.deliver(&format!("{}.json", name), &record)?;
+        Ok(())
+    }
+}
+
+/// Reads the records of a Maildir history from `new/` and `cur/`, oldest
+/// first.
+pub fn read_maildir<P: AsRef<Path>>(dir: P) -> Result<Vec<ClipboardData>, Error> {
+    let dir = dir.as_ref();
+    let mut paths = vec![];
+    for sub in &["new", "cur"] {
+        let entries = match fs::read_dir(dir.join(sub)) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            // Maildir readers append flags such as `:2,S` to the names
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split(':').next())
+                .unwrap_or_default();
+            if let Some(stem) = name.strip_suffix(".json") {
+                let created_at = stem
+                    .split(['.', '-'])
+                    .next()
+                    .and_then(|created_at| created_at.parse::<u64>().ok());
+                paths.push((created_at, path));
+            }
+        }
+    }
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|(_, path)| {
+            let file = File::open(&path)?;
+            serde_json::from_reader(BufReader::new(file))
+                .map_err(|e| format_err!("Could not read {}: {}", path.display(), e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deliver() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = MaildirSink::new(dir.path());
+        let html = ClipboardData::new(("<b>Hello</b>".to_string(), None, None));
+        sink.write(&ClipboardData::new(("Hello".to_string(), None)))
+            .unwrap();
+        sink.write(&html).unwrap();
+
+        let names = fs::read_dir(dir.path().join("new"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 4);
+        let payload = names.iter().find(|name| name.ends_with(".html")).unwrap();
+        let payload = fs::read_to_string(dir.path().join("new").join(payload)).unwrap();
+        assert_eq!(payload, "<b>Hello</b>");
+        assert_eq!(fs::read_dir(dir.path().join("tmp")).unwrap().count(), 0);
+
+        let records = read_maildir(dir.path()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().any(|data| data.content() == "Hello"));
+    }
+
+    #[test]
+    fn test_deliver_same_record_twice() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = MaildirSink::new(dir.path());
+        let data = ClipboardData::new(("Hello".to_string(), None));
+        sink.write(&data).unwrap();
+        sink.write(&data).unwrap();
+
+        assert_eq!(fs::read_dir(dir.path().join("new")).unwrap().count(), 4);
+        let records = read_maildir(dir.path()).unwrap();
+        assert_eq!(records, [data.clone(), data]);
+    }
+}
@@ -1,13 +1,86 @@
-mod utils;
+mod cli;
 
-use clipboard2json::{Clipboard, ClipboardFunctions, ClipboardSink};
+use clipboard2json::{
+    rekey, BackendKind, Cipher, Clipboard, Config, Pipeline, SinkConfig, NEW_PASSPHRASE_VAR,
+    PASSPHRASE_VAR,
+};
+use failure::Error;
+use std::fs;
+use std::path::Path;
+use std::process;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("Welcome to Clipboard2JSON!");
-    let dpy = Clipboard::new()?;
-    println!(
-        "\nTry copying some text and it should show up in a clipboard.json file in your folder\n"
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let options = cli::parse(std::env::args().skip(1))?;
+    if options.help {
+        println!("{}", cli::usage());
+        return Ok(());
+    }
+
+    // An explicitly passed config must exist while the default one is optional
+    let mut config = match options.config {
+        Some(path) => Config::load(path)?,
+        None => match Config::default_path() {
+            Some(ref path) if path.exists() => Config::load(path)?,
+            _ => Config::default(),
+        },
+    };
+    let data_dir = config.resolve_data_dir(options.data_dir)?;
+    // Older versions wrote the history to the current directory
+    let legacy = Path::new("clipboard.json");
+    let moved = data_dir.join(legacy);
+    if legacy.exists() && !moved.exists() && fs::canonicalize(".")? != fs::canonicalize(&data_dir)?
+    {
+        eprintln!(
+            "WARNING: {} in the current directory was written by an older version. \
+             The history is now stored in {}, move the file there to keep it.",
+            legacy.display(),
+            data_dir.display()
+        );
+    }
+    if options.rekey {
+        let old = Cipher::from_env(PASSPHRASE_VAR)?;
+        let new = Cipher::from_env(NEW_PASSPHRASE_VAR)?;
+        for sink in &config.pipeline.sinks {
+            if let SinkConfig::File { path, .. } = sink {
+                let records = rekey(path, old.as_ref(), new.as_ref())?;
+                let state = if new.is_some() {
+                    "encrypted"
+                } else {
+                    "decrypted"
+                };
+                println!("{}: {} records {}", path.display(), records, state);
+            }
+        }
+        return Ok(());
+    }
+    if options.prune {
+        for sink in &config.pipeline.sinks {
+            let mut sink = sink.build()?;
+            if let Some(pruned) = sink.prune()? {
+                println!("{}: {}", sink.name(), pruned);
+            }
+        }
+        return Ok(());
+    }
+    let backend = options
+        .backend
+        .or(config.backend)
+        .unwrap_or(BackendKind::Auto);
+
+    eprintln!("Welcome to Clipboard2JSON!");
+    let dpy = Clipboard::open(backend)?;
+    let pipeline = Pipeline::from_config(&config.pipeline, dpy.writer())?;
+    eprintln!(
+        "\nTry copying some text and it should show up in the history in {}\n",
+        data_dir.display()
     );
-    dpy.watch_clipboard(&ClipboardSink(utils::save_clipboard_to_file));
+    dpy.watch_selections(&pipeline.into_sink(), &config.watch);
     Ok(())
 }
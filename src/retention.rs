@@ -0,0 +1,255 @@
+use crate::classify::Kind;
+use crate::common::ClipboardData;
+use failure::{bail, Error};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::time::Duration;
+
+/// How much of the history is kept. Every limit is optional and the history
+/// is kept forever when none is set.
+///
+/// The age of an entry is counted from the latest time it was copied, so
+/// content that is copied again is kept longer.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retention {
+    /// Number of entries to keep, the oldest ones are dropped first
+    pub max_entries: Option<usize>,
+    /// Entries that were last copied longer ago are dropped, e.g. `"30d"`
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub max_age: Option<Duration>,
+    /// Size of the records in bytes, the oldest entries are dropped first
+    pub max_bytes: Option<u64>,
+    /// Kinds of content that are kept for `sensitive_max_age` only. Entries
+    /// with redacted secrets are always sensitive.
+    pub sensitive_kinds: Vec<Kind>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub sensitive_max_age: Option<Duration>,
+}
+
+impl Retention {
+    /// Whether no limit is set.
+    pub fn is_empty(&self) -> bool {
+        self.max_entries.is_none()
+            && self.max_age.is_none()
+            && self.max_bytes.is_none()
+            && self.sensitive_max_age.is_none()
+    }
+
+    /// Whether a history of `entries` entries taking `bytes` bytes is over
+    /// `max_entries` or `max_bytes`.
+    pub fn is_over(&self, entries: usize, bytes: u64) -> bool {
+        self.max_entries.is_some_and(|max| entries > max)
+            || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+
+    /// How often the age limits are enforced, a tenth of the shortest one
+    /// but at least once an hour.
+    pub fn age_check_interval(&self) -> Option<Duration> {
+        self.max_age
+            .into_iter()
+            .chain(self.sensitive_max_age)
+            .min()
+            .map(|age| (age / 10).clamp(Duration::from_secs(1), Duration::from_secs(3600)))
+    }
+
+    pub fn is_sensitive(&self, data: &ClipboardData) -> bool {
+        let meta = data.meta();
+        !meta.redactions.is_empty()
+            || meta
+                .kind
+                .is_some_and(|kind| self.sensitive_kinds.contains(&kind))
+    }
+
+    /// Drops the entries that are past the limits at `now`, a timestamp in
+    /// seconds.
+    pub fn apply(&self, entries: Vec<ClipboardData>, now: u64) -> (Vec<ClipboardData>, Pruned) {
+        let (keep, pruned) = self.select(&entries, now);
+        let kept = entries
+            .into_iter()
+            .zip(keep)
+            .filter_map(|(data, keep)| Some(data).filter(|_| keep))
+            .collect();
+        (kept, pruned)
+    }
+
+    /// Tells which entries to keep at `now`. Sensitive and old entries are
+    /// dropped first, then the oldest entries over `max_entries` and
+    /// `max_bytes`.
+    pub fn select(&self, entries: &[ClipboardData], now: u64) -> (Vec<bool>, Pruned) {
+        let mut pruned = Pruned::default();
+        let cutoff = |age: Option<Duration>| age.map(|age| now.saturating_sub(age.as_secs()));
+        let sensitive_cutoff = cutoff(self.sensitive_max_age);
+        let cutoff = cutoff(self.max_age);
+        let mut keep = entries
+            .iter()
+            .map(|data| {
+                let copied_at = data.meta().last_copied_at.unwrap_or(data.created_at());
+                if sensitive_cutoff.is_some_and(|cutoff| copied_at < cutoff)
+                    && self.is_sensitive(data)
+                {
+                    pruned.sensitive += 1;
+                    false
+                } else if cutoff.is_some_and(|cutoff| copied_at < cutoff) {
+                    pruned.expired += 1;
+                    false
+                } else {
+                    true
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // The newest entries that are left are kept within the limits
+        let mut count = 0;
+        let mut size = 0;
+        for (data, keep) in entries.iter().zip(keep.iter_mut()).rev() {
+            if !*keep {
+                continue;
+            }
+            count += 1;
+            if self.max_entries.is_some_and(|max| count > max) {
+                pruned.over_max_entries += 1;
+                *keep = false;
+                continue;
+            }
+            // Every record is stored on its own line
+            size += serde_json::to_vec(data).map_or(0, |json| json.len() as u64 + 1);
+            if self.max_bytes.is_some_and(|max| size > max) {
+                pruned.over_max_bytes += 1;
+                *keep = false;
+            }
+        }
+        (keep, pruned)
+    }
+}
+
+/// Number of entries dropped by a `Retention`, by the limit that dropped them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pruned {
+    /// Sensitive entries older than `sensitive_max_age`
+    pub sensitive: usize,
+    /// Entries older than `max_age`
+    pub expired: usize,
+    pub over_max_entries: usize,
+    pub over_max_bytes: usize,
+}
+
+impl Pruned {
+    pub fn total(&self) -> usize {
+        self.sensitive + self.expired + self.over_max_entries + self.over_max_bytes
+    }
+}
+
+impl fmt::Display for Pruned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} entries pruned ({} sensitive, {} expired, {} over max_entries, {} over max_bytes)",
+            self.total(),
+            self.sensitive,
+            self.expired,
+            self.over_max_entries,
+            self.over_max_bytes
+        )
+    }
+}
+
+/// Parses a duration such as `90s`, `15m`, `12h`, `30d` or `2w`.
+pub fn parse_duration(text: &str) -> Result<Duration, Error> {
+    let text = text.trim();
+    let unit = match text.char_indices().last() {
+        Some((i, unit)) if unit.is_ascii_alphabetic() => (i, unit),
+        _ => bail!("Missing unit in duration '{}'", text),
+    };
+    let seconds = match unit.1 {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => bail!("Unknown unit in duration '{}', use s, m, h, d or w", text),
+    };
+    match text[..unit.0].trim().parse::<u64>() {
+        Ok(count) => Ok(Duration::from_secs(count.saturating_mul(seconds))),
+        Err(_) => bail!("Invalid duration '{}'", text),
+    }
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text: Option<String> = Option::deserialize(deserializer)?;
+    text.map(|text| parse_duration(&text).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copy(content: &str, created_at: u64) -> ClipboardData {
+        let mut data = ClipboardData::new((content.to_string(), None));
+        if let ClipboardData::UnicodeText { created_at: at, .. } = &mut data {
+            *at = created_at;
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_retention() {
+        let retention: Retention = serde_json::from_str(
+            r#"{ "max_entries": 100, "max_age": "30d", "sensitive_kinds": ["email"],
+                 "sensitive_max_age": "1h" }"#,
+        )
+        .unwrap();
+        assert_eq!(retention.max_age, Some(Duration::from_secs(30 * 86400)));
+        assert_eq!(retention.sensitive_max_age, Some(Duration::from_secs(3600)));
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("3y").is_err());
+        assert!(serde_json::from_str::<Retention>(r#"{ "max_age": "soon" }"#).is_err());
+    }
+
+    #[test]
+    fn test_apply_limits() {
+        let now = 10 * 86400;
+        let mut recopied = copy("old but copied again", 0);
+        recopied.meta_mut().last_copied_at = Some(now - 60);
+        let entries = vec![
+            copy("too old", 0),
+            recopied,
+            copy("someone@example.com", now - 7200),
+            copy("a", now - 50),
+            copy("b", now - 40),
+            copy("c", now - 30),
+        ];
+        let retention = Retention {
+            max_entries: Some(3),
+            max_age: Some(Duration::from_secs(86400)),
+            sensitive_kinds: vec![Kind::Email],
+            sensitive_max_age: Some(Duration::from_secs(3600)),
+            ..Retention::default()
+        };
+        let (kept, pruned) = retention.apply(entries.clone(), now);
+        let kept = kept.iter().map(ClipboardData::content).collect::<Vec<_>>();
+        assert_eq!(kept, vec!["a", "b", "c"]);
+        assert_eq!(
+            pruned,
+            Pruned {
+                sensitive: 1,
+                expired: 1,
+                over_max_entries: 1,
+                over_max_bytes: 0
+            }
+        );
+
+        let size = serde_json::to_vec(&entries[5]).unwrap().len() as u64 + 1;
+        let retention = Retention {
+            max_bytes: Some(size * 3 / 2),
+            ..Retention::default()
+        };
+        let (kept, pruned) = retention.apply(entries, now);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(pruned.over_max_bytes, 5);
+    }
+}
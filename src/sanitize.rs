@@ -0,0 +1,289 @@
+use crate::common::ClipboardData;
+use crate::pipeline::Stage;
+use ego_tree::NodeRef;
+use failure::Error;
+use scraper::{Html, Node};
+use serde::Deserialize;
+
+/// Elements that are removed along with everything inside of them.
+const DROPPED_TAGS: &[&str] = &[
+    "script", "style", "head", "title", "iframe", "frame", "frameset", "object", "embed", "applet",
+    "noscript", "template", "textarea", "select", "svg", "math", "meta", "link", "base",
+];
+
+/// Elements that never have content or a closing tag.
+const VOID_TAGS: &[&str] = &["area", "br", "col", "hr", "img", "wbr"];
+
+/// Attributes holding a URL whose scheme is checked.
+const URL_ATTRIBUTES: &[&str] = &["href", "src", "cite", "longdesc"];
+
+/// Stage that cleans up HTML data with an allow-list policy so that it can be
+/// displayed safely. Text data is passed through unchanged.
+///
+/// Elements that are not allowed are replaced with their content, except for
+/// scripts, styles, frames, embedded objects and the like which are removed
+/// entirely. Event handler attributes are always removed.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sanitizer {
+    /// Elements that are kept
+    pub tags: Vec<String>,
+    /// Attributes that are kept on the allowed elements
+    pub attributes: Vec<String>,
+    /// Schemes allowed in links and image sources. Relative URLs are always
+    /// allowed.
+    pub url_schemes: Vec<String>,
+    /// Removes `style` attributes even when they are in `attributes`
+    pub strip_styles: bool,
+    /// Removes `class` attributes even when they are in `attributes`
+    pub strip_classes: bool,
+    /// Removes images loaded from another host, which are often used for
+    /// tracking
+    pub remove_remote_images: bool,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        let strings = |items: &[&str]| items.iter().map(|item| item.to_string()).collect();
+        Sanitizer {
+            tags: strings(&[
+                "a",
+                "abbr",
+                "b",
+                "blockquote",
+                "br",
+                "caption",
+                "code",
+                "col",
+                "colgroup",
+                "dd",
+                "del",
+                "div",
+                "dl",
+                "dt",
+                "em",
+                "figcaption",
+                "figure",
+                "h1",
+                "h2",
+                "h3",
+                "h4",
+                "h5",
+                "h6",
+                "hr",
+                "i",
+                "img",
+                "ins",
+                "kbd",
+                "li",
+                "mark",
+                "ol",
+                "p",
+                "pre",
+                "q",
+                "s",
+                "samp",
+                "small",
+                "span",
+                "strike",
+                "strong",
+                "sub",
+                "sup",
+                "table",
+                "tbody",
+                "td",
+                "tfoot",
+                "th",
+                "thead",
+                "tr",
+                "u",
+                "ul",
+            ]),
+            attributes: strings(&[
+                "alt", "cite", "colspan", "dir", "height", "href", "lang", "rowspan", "span",
+                "src", "start", "title", "width",
+            ]),
+            url_schemes: strings(&["http", "https", "mailto"]),
+            strip_styles: true,
+            strip_classes: true,
+            remove_remote_images: true,
+        }
+    }
+}
+
+impl Sanitizer {
+    /// Returns the sanitised HTML fragment.
+    pub fn sanitize(&self, html: &str) -> String {
+        let document = Html::parse_fragment(html);
+        let mut output = String::with_capacity(html.len());
+        for child in document.root_element().children() {
+            self.write(child, &mut output);
+        }
+        output
+    }
+
+    fn write(&self, node: NodeRef<Node>, output: &mut String) {
+        let element = match node.value() {
+            Node::Text(text) => {
+                escape(text, false, output);
+                return;
+            }
+            Node::Element(element) => element,
+            // Comments can hide conditional markup and are dropped
+            _ => return,
+        };
+
+        let name = element.name();
+        if DROPPED_TAGS.contains(&name) {
+            return;
+        }
+        if !self.tags.iter().any(|tag| tag.eq_ignore_ascii_case(name)) {
+            for child in node.children() {
+                self.write(child, output);
+            }
+            return;
+        }
+
+        let attributes = element
+            .attrs()
+            .filter(|(attribute, value)| self.allows_attribute(attribute, value))
+            .collect::<Vec<_>>();
+        if name == "img" {
+            let src = attributes.iter().find(|(attribute, _)| *attribute == "src");
+            let remote = src.is_some_and(|(_, src)| is_remote(src));
+            if src.is_none() || (remote && self.remove_remote_images) {
+                return;
+            }
+        }
+
+        output.push('<');
+        output.push_str(name);
+        for (attribute, value) in attributes {
+            output.push(' ');
+            output.push_str(attribute);
+            output.push_str("=\"");
+            escape(value, true, output);
+            output.push('"');
+        }
+        output.push('>');
+        if VOID_TAGS.contains(&name) {
+            return;
+        }
+        for child in node.children() {
+            self.write(child, output);
+        }
+        output.push_str("</");
+        output.push_str(name);
+        output.push('>');
+    }
+
+    fn allows_attribute(&self, attribute: &str, value: &str) -> bool {
+        if attribute.starts_with("on")
+            || (attribute == "style" && self.strip_styles)
+            || (attribute == "class" && self.strip_classes)
+            || !self.attributes.iter().any(|name| name == attribute)
+        {
+            return false;
+        }
+        if !URL_ATTRIBUTES.contains(&attribute) {
+            return true;
+        }
+        match url_scheme(value) {
+            Some(scheme) => self
+                .url_schemes
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(&scheme)),
+            None => true,
+        }
+    }
+}
+
+impl Stage for Sanitizer {
+    fn process(&mut self, mut data: ClipboardData) -> Result<Option<ClipboardData>, Error> {
+        if let ClipboardData::Html { content, .. } = &mut data {
+            *content = self.sanitize(content);
+            data.refresh();
+        }
+        Ok(Some(data))
+    }
+}
+
+/// Scheme of the URL, if it is not relative. Browsers ignore whitespace and
+/// control characters in URLs, so `java&#9;script:` is a scheme too.
+fn url_scheme(url: &str) -> Option<String> {
+    let url = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect::<String>();
+    let colon = url.find(':')?;
+    let scheme = &url[..colon];
+    // A colon after a path separator or a query is part of a relative URL
+    let valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    if valid {
+        Some(scheme.to_string())
+    } else {
+        None
+    }
+}
+
+fn is_remote(url: &str) -> bool {
+    let url = url.trim();
+    url.starts_with("//") || url_scheme(url).is_some_and(|scheme| scheme != "data")
+}
+
+fn escape(text: &str, attribute: bool, output: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' if !attribute => output.push_str("&lt;"),
+            '>' if !attribute => output.push_str("&gt;"),
+            '"' if attribute => output.push_str("&quot;"),
+            '\u{a0}' => output.push_str("&nbsp;"),
+            c => output.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        let sanitizer = Sanitizer::default();
+        let html = concat!(
+            r#"<div class="post" style="font-size: 40px" onclick="steal()">"#,
+            r#"<script>alert(1)</script><p>Hi <a href="java&#9;script:alert(1)">there</a>, "#,
+            r#"<a href="/docs" target="_blank">docs</a> &amp; <font color="red">more</font></p>"#,
+            r#"<img src="https://tracker.example/pixel.gif" width="1" height="1">"#,
+            r#"<img src="data:text/html;base64,PHNjcmlwdD4="><img src="logo.png" alt="Logo">"#,
+            "<!-- note --></div>"
+        );
+        assert_eq!(
+            sanitizer.sanitize(html),
+            concat!(
+                r#"<div><p>Hi <a>there</a>, <a href="/docs">docs</a> &amp; more</p>"#,
+                r#"<img alt="Logo" src="logo.png"></div>"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_policy_from_config() {
+        let sanitizer: Sanitizer = serde_json::from_str(
+            r#"{ "tags": ["p", "img"], "remove_remote_images": false, "url_schemes": ["https"] }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            sanitizer.sanitize(r#"<p><b>bold</b><img src="https://example.com/a.png"></p>"#),
+            r#"<p>bold<img src="https://example.com/a.png"></p>"#
+        );
+        assert_eq!(
+            sanitizer.sanitize(r#"<img src="http://example.com/a.png">"#),
+            ""
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use crate::store::private_options;
+use chrono::{Local, TimeZone, Utc};
+use failure::{format_err, Error};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// When the live history is closed and archived as a compressed segment.
+/// Segments are never rolled over when neither limit is set.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rollover {
+    /// Starts a new segment on the first copy of every day, in local time
+    pub daily: bool,
+    /// Starts a new segment once the live history is larger
+    pub max_bytes: Option<u64>,
+    pub compression: Compression,
+}
+
+impl Rollover {
+    pub fn is_empty(&self) -> bool {
+        !self.daily && self.max_bytes.is_none()
+    }
+
+    /// Whether a live history of the given size, whose first record was
+    /// created at `first`, has to be closed at `now`. Timestamps are in
+    /// seconds.
+    pub fn is_due(&self, size: u64, first: u64, now: u64) -> bool {
+        let day = |timestamp: u64| {
+            Local
+                .timestamp_opt(timestamp as i64, 0)
+                .single()
+                .map(|time| time.date_naive())
+        };
+        size > 0
+            && (self.max_bytes.is_some_and(|max| size >= max)
+                || self.daily && day(first) != day(now))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+}
+
+/// Closed segments of the history at `path`, oldest first.
+///
+/// A segment is named `<path>.<timestamp>` after the time it was closed, in
+/// UTC, and gets the extension of its compression once it is compressed. A
+/// segment that is left uncompressed by a crash is only listed when its
+/// compressed copy is missing.
+pub fn list_segments(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    Ok(stamped_segments(path)?
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// Timestamp of a segment and the number that tells apart segments closed
+/// within the same second.
+type Stamp = (String, u64);
+
+/// Closed segments of the history with their stamps, oldest first.
+fn stamped_segments(path: &Path) -> Result<Vec<(Stamp, PathBuf)>, Error> {
+    let (dir, name) = split(path)?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut segments = vec![];
+    for entry in entries {
+        let file_name = entry?.file_name();
+        let stamp = match file_name
+            .to_str()
+            .and_then(|file_name| file_name.strip_prefix(&name))
+            .and_then(|rest| rest.strip_prefix('.'))
+        {
+            Some(stamp) => stamp.to_string(),
+            None => continue,
+        };
+        let (stamp, compressed) = match stamp.rsplit_once('.') {
+            Some((stamp, "gz")) | Some((stamp, "zst")) => (stamp.to_string(), true),
+            Some(_) => continue,
+            None => (stamp, false),
+        };
+        if is_stamp(&stamp) {
+            let key = match stamp.split_once('-') {
+                Some((time, attempt)) => (time.to_string(), attempt.parse().unwrap_or(0)),
+                None => (stamp, 1u64),
+            };
+            segments.push((key, !compressed, dir.join(file_name)));
+        }
+    }
+    // The compressed copy of a segment comes first and hides the other
+    segments.sort();
+    segments.dedup_by(|next, first| next.0 == first.0);
+    Ok(segments
+        .into_iter()
+        .map(|(key, _, path)| (key, path))
+        .collect())
+}
+
+/// Closes the live history by moving it to a new segment, which is then
+/// compressed. The caller has to hold an exclusive lock.
+pub fn roll_over(path: &Path, compression: Compression) -> Result<PathBuf, Error> {
+    // In UTC, so that the names sort in order across changes of the time
+    // zone or of daylight saving time
+    let mut stamp = Utc::now().format("%Y%m%dT%H%M%S").to_string();
+    let mut attempt = 1;
+    // The new segment has to come after the others, even when one that was
+    // closed within the same second has been removed since
+    if let Some(((last, last_attempt), _)) = stamped_segments(path)?.pop() {
+        if last >= stamp {
+            stamp = last;
+            attempt = last_attempt + 1;
+        }
+    }
+    let segment = match attempt {
+        1 => format!("{}.{}", path.display(), stamp),
+        _ => format!("{}.{}-{}", path.display(), stamp, attempt),
+    };
+    let segment = PathBuf::from(segment);
+    fs::rename(path, &segment)
+        .map_err(|e| format_err!("Could not close {}: {}", path.display(), e))?;
+    compress_pending(path, compression)?;
+    Ok(segment)
+}
+
+/// Compresses the segments that are still uncompressed, including those
+/// left behind by a crash.
+fn compress_pending(path: &Path, compression: Compression) -> Result<(), Error> {
+    for segment in list_segments(path)? {
+        if segment
+            .extension()
+            .is_some_and(|extension| extension == "gz" || extension == "zst")
+        {
+            continue;
+        }
+        let mut compressed = segment.clone().into_os_string();
+        compressed.push(".");
+        compressed.push(compression.extension());
+        let compressed = PathBuf::from(compressed);
+        let mut records = File::open(&segment)?;
+        write_compressed(&compressed, compression, |writer| {
+            io::copy(&mut records, writer).map(|_| ())
+        })?;
+        fs::remove_file(&segment)?;
+    }
+    Ok(())
+}
+
+/// Replaces the segment with the lines written by `write`, compressed like
+/// the segment. The lines go to a temporary file first so that the segment
+/// is never left half written.
+pub fn rewrite_segment<F>(segment: &Path, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let compression = match segment.extension().and_then(|extension| extension.to_str()) {
+        Some("zst") => Compression::Zstd,
+        _ => Compression::Gzip,
+    };
+    write_compressed(segment, compression, write)
+}
+
+fn write_compressed<F>(path: &Path, compression: Compression, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let file = private_options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&temp)?;
+    let file = match compression {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(BufWriter::new(file), flate2::Compression::default());
+            write(&mut encoder)?;
+            encoder.finish()?
+        }
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(BufWriter::new(file), 0)?;
+            write(&mut encoder)?;
+            encoder.finish()?
+        }
+    };
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&temp, path)?;
+    sync_dir(path)
+}
+
+/// Opens a segment, decompressing it according to its extension. Data that
+/// cannot be decompressed, such as a truncated segment, is reported as
+/// `io::ErrorKind::InvalidData`.
+pub fn open_segment(segment: &Path) -> Result<Box<dyn Read + Send>, Error> {
+    let file = File::open(segment)
+        .map_err(|e| format_err!("Could not open {}: {}", segment.display(), e))?;
+    Ok(
+        match segment.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Box::new(Decompressed(GzDecoder::new(BufReader::new(file)))),
+            Some("zst") => Box::new(Decompressed(zstd::Decoder::new(file)?)),
+            _ => Box::new(file),
+        },
+    )
+}
+
+struct Decompressed<R>(R);
+
+impl<R: Read> Read for Decompressed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::Interrupted => e,
+            _ => io::Error::new(io::ErrorKind::InvalidData, e),
+        })
+    }
+}
+
+/// Makes a rename in the directory of the path durable.
+pub(crate) fn sync_dir(path: &Path) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+fn split(path: &Path) -> Result<(PathBuf, String), Error> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format_err!("Invalid history path {}", path.display()))?;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    Ok((dir, name.to_string()))
+}
+
+/// Whether the text is a timestamp such as `20240314T093000`, optionally
+/// followed by `-<n>`.
+fn is_stamp(text: &str) -> bool {
+    let stamp = text.split('-').next().unwrap_or_default();
+    let (date, time) = match stamp.split_once('T') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    date.len() == 8
+        && time.len() == 6
+        && date
+            .bytes()
+            .chain(time.bytes())
+            .all(|byte| byte.is_ascii_digit())
+        && text[stamp.len()..].strip_prefix('-').is_none_or(|attempt| {
+            !attempt.is_empty() && attempt.bytes().all(|byte| byte.is_ascii_digit())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stamps() {
+        assert!(is_stamp("20240314T093000"));
+        assert!(is_stamp("20240314T093000-12"));
+        for stamp in &[
+            "20240314",
+            "20240314T0930",
+            "2024031xT093000",
+            "20240314T093000-",
+            "20240314T093000-a",
+            "corrupt-20240314T093000",
+        ] {
+            assert!(!is_stamp(stamp), "{}", stamp);
+        }
+    }
+
+    #[test]
+    fn test_list_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        for name in &[
+            "clipboard.json",
+            "clipboard.json.20240314T093000-10.gz",
+            "clipboard.json.20240314T093000-2.zst",
+            "clipboard.json.20240314T093000.gz",
+            "clipboard.json.20240101T000000",
+            "clipboard.json.20240101T000000.gz",
+            "clipboard.json.20240102T000000",
+            "clipboard.json.20240103T000000.gz.tmp",
+            "clipboard.json.20240104T000000.gz.corrupt-20240105T000000",
+            "clipboard.json.corrupt-20240105T000000",
+            "other.json.20240101T000000.gz",
+        ] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        let names = list_segments(&path)
+            .unwrap()
+            .iter()
+            .map(|segment| segment.file_name().unwrap().to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "clipboard.json.20240101T000000.gz",
+                "clipboard.json.20240102T000000",
+                "clipboard.json.20240314T093000.gz",
+                "clipboard.json.20240314T093000-2.zst",
+                "clipboard.json.20240314T093000-10.gz",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_due() {
+        let noon = Local
+            .with_ymd_and_hms(2024, 3, 14, 12, 0, 0)
+            .unwrap()
+            .timestamp() as u64;
+        assert!(!Rollover::default().is_due(100, noon, noon + 86400));
+        let daily = Rollover {
+            daily: true,
+            ..Rollover::default()
+        };
+        assert!(!daily.is_due(100, noon, noon + 3600));
+        assert!(daily.is_due(100, noon, noon + 86400));
+        assert!(!daily.is_due(0, noon, noon + 86400));
+        let sized = Rollover {
+            max_bytes: Some(100),
+            ..Rollover::default()
+        };
+        assert!(!sized.is_due(99, noon, noon + 86400));
+        assert!(sized.is_due(100, noon, noon));
+        let both = Rollover {
+            daily: true,
+            ..sized
+        };
+        assert!(both.is_due(100, noon, noon));
+        assert!(both.is_due(1, noon, noon + 86400));
+        assert!(!both.is_due(1, noon, noon + 3600));
+    }
+
+    #[test]
+    fn test_roll_over_and_rewrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        let read = |segment: &Path| {
+            let mut text = String::new();
+            open_segment(segment)
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+        for (compression, extension) in &[(Compression::Gzip, "gz"), (Compression::Zstd, "zst")] {
+            fs::write(&path, "a\nb\n").unwrap();
+            roll_over(&path, *compression).unwrap();
+            assert!(!path.exists());
+            let segment = list_segments(&path).unwrap().pop().unwrap();
+            assert_eq!(segment.extension().unwrap(), *extension);
+            assert_eq!(read(&segment), "a\nb\n");
+
+            rewrite_segment(&segment, |writer| writer.write_all(b"b\n")).unwrap();
+            assert_eq!(read(&segment), "b\n");
+        }
+        // Segments closed within the same second come after each other
+        let segments = list_segments(&path).unwrap();
+        assert_eq!(segments.len(), 2);
+        assert!(segments[1].to_str().unwrap().ends_with("-2.zst"));
+
+        // A truncated segment is invalid data rather than a short read
+        let bytes = fs::read(&segments[0]).unwrap();
+        fs::write(&segments[0], &bytes[..bytes.len() / 2]).unwrap();
+        let err = open_segment(&segments[0])
+            .unwrap()
+            .read_to_end(&mut vec![])
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,334 @@
+use crate::blobs::BlobStore;
+use crate::common::{get_created_timestamp, ClipboardData};
+use crate::crypto::Cipher;
+use crate::dedup::DedupMode;
+use crate::pipeline::Sink;
+use crate::retention::{Pruned, Retention};
+use crate::segments::Rollover;
+use crate::store::{DamagedRecord, HistoryIndex, HistoryLock};
+use failure::{bail, format_err, Error};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Stores the clipboard history in a file as JSON Lines, one record per line.
+///
+/// Histories written as a JSON array by older versions are converted the
+/// first time the sink writes to them. The retention limits are enforced
+/// on every write that takes the history over `max_entries` or `max_bytes`,
+/// as told by the index, and the age limits every tenth of the shortest
+/// age, so that the whole history is only read when something has to be
+/// dropped. With a cipher every record is encrypted, including
+/// those of a plain text history that the sink writes to. With a rollover
+/// the history is closed into a compressed segment once it is due. Content
+/// larger than the blob threshold is moved to the blob store next to the
+/// history, unless the history is encrypted.
+pub struct FileSink {
+    path: PathBuf,
+    dedup: DedupMode,
+    retention: Retention,
+    rollover: Rollover,
+    blob_threshold: Option<u64>,
+    cipher: Option<Cipher>,
+    /// Loaded on the first write
+    index: Option<HistoryIndex>,
+    /// Time in seconds at which the age limits are enforced next
+    next_age_check: u64,
+}
+
+impl FileSink {
+    /// Creates a sink that merges repeated copies into the existing entry.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        FileSink::with_dedup(path, DedupMode::default())
+    }
+
+    pub fn with_dedup<P: Into<PathBuf>>(path: P, dedup: DedupMode) -> Self {
+        FileSink {
+            path: path.into(),
+            dedup,
+            retention: Retention::default(),
+            rollover: Rollover::default(),
+            blob_threshold: None,
+            cipher: None,
+            index: None,
+            next_age_check: 0,
+        }
+    }
+
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn with_rollover(mut self, rollover: Rollover) -> Self {
+        self.rollover = rollover;
+        self
+    }
+
+    /// Moves content larger than `threshold` bytes to the blob store.
+    pub fn with_blob_threshold(mut self, threshold: u64) -> Self {
+        self.blob_threshold = Some(threshold);
+        self
+    }
+
+    pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+}
+
+impl Sink for FileSink {
+    fn name(&self) -> String {
+        format!("file {}", self.path.display())
+    }
+
+    /// Appends the data to the file while holding an exclusive lock on it. A
+    /// repeated copy in the `merge` dedup mode is appended with the bumped
+    /// `copy_count`, which `read_history` merges into the entry of the first
+    /// copy. Nothing is written when the data is a duplicate that the dedup
+    /// mode skips. Copies appended by other processes count as well.
+    fn write(&mut self, data: &ClipboardData) -> Result<(), Error> {
+        let path = &self.path;
+        let lock = HistoryLock::exclusive(path)?.with_cipher(self.cipher.clone());
+        let index = self.index.get_or_insert_with(HistoryIndex::default);
+        // Only the first write has to check the format of the file
+        let mut updated = Ok(());
+        if index.is_empty() {
+            updated = lock.migrate().map(|_| ());
+        }
+        if let Err(e) = updated.and_then(|_| index.update(&lock)) {
+            let damage = match e.downcast_ref::<DamagedRecord>() {
+                Some(damage) => damage,
+                None => return Err(e),
+            };
+            // The damaged files are never written to, only moved aside
+            eprintln!(
+                "WARNING: The history in {} is damaged ({}).",
+                path.display(),
+                damage
+            );
+            for recovery in lock.recover()? {
+                eprintln!("WARNING: {}.", recovery);
+            }
+            *index = HistoryIndex::default();
+            index.update(&lock)?;
+        }
+        // The index already covers the records of the closed segment
+        if lock.roll_over(&self.rollover, data.created_at())?.is_some() {
+            index.rolled_over(&lock)?;
+        }
+
+        let mut data = data.clone();
+        if data.meta().content_hash.is_empty() {
+            data.refresh_hash();
+        }
+        let hash = data.meta().content_hash.clone();
+        match self.dedup {
+            DedupMode::Off => {}
+            DedupMode::Merge => {
+                let copies = index.copies(&hash);
+                if copies > 0 {
+                    let created_at = data.created_at();
+                    let meta = data.meta_mut();
+                    meta.copy_count = copies + 1;
+                    meta.last_copied_at = Some(created_at);
+                }
+            }
+            DedupMode::SkipConsecutive => {
+                if index.last_hash() == Some(hash.as_str()) {
+                    return Ok(());
+                }
+            }
+        }
+        if let (Some(threshold), None) = (self.blob_threshold, &self.cipher) {
+            BlobStore::new(path).offload(&mut data, threshold)?;
+        }
+        lock.append(&data)?;
+        index.update(&lock)?;
+
+        let now = get_created_timestamp();
+        let retention = &self.retention;
+        let next_age_check = self.next_age_check;
+        let age_check = retention
+            .age_check_interval()
+            .filter(|_| now >= next_age_check);
+        if let Some(interval) = age_check {
+            self.next_age_check = now + interval.as_secs();
+        }
+        if (age_check.is_some() || retention.is_over(index.entries(), index.bytes()))
+            && lock.prune(retention, now)?.total() > 0
+        {
+            index.update(&lock)?;
+        }
+        Ok(())
+    }
+
+    fn prune(&mut self) -> Result<Option<Pruned>, Error> {
+        let lock = HistoryLock::exclusive(&self.path)?.with_cipher(self.cipher.clone());
+        // The pruned history is indexed again on the next write
+        self.index = None;
+        Ok(Some(lock.prune(&self.retention, get_created_timestamp())?))
+    }
+}
+
+/// Prints every clipboard change to stdout as a single line of JSON, which
+/// makes it easy to pipe the changes into other programs.
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
+
+    fn write(&mut self, data: &ClipboardData) -> Result<(), Error> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        serde_json::to_writer(&mut stdout, data)?;
+        writeln!(stdout)?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Sends every clipboard change to an external API as a JSON `POST` request.
+///
+/// Only plain `http://` URLs are supported, which is enough for collectors
+/// running on the same machine or in the same network.
+pub struct HttpSink {
+    url: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl HttpSink {
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let rest = match url.find("://") {
+            Some(i) if &url[..i] == "http" => &url[i + 3..],
+            Some(_) => bail!("Only http:// URLs are supported, got {}", url),
+            None => bail!("Invalid URL {}", url),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        // The colons of an IPv6 host are inside its brackets
+        let host_end = match authority.strip_prefix('[') {
+            Some(rest) => match rest.find(']') {
+                Some(i) => i + 2,
+                None => bail!("Invalid host in URL {}", url),
+            },
+            None => 0,
+        };
+        let (host, port) = match authority[host_end..].rfind(':').map(|i| host_end + i) {
+            Some(i) => (
+                &authority[..i],
+                authority[i + 1..]
+                    .parse()
+                    .map_err(|_| format_err!("Invalid port in URL {}", url))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() || host == "[]" || (host_end > 0 && host.len() != host_end) {
+            bail!("Missing host in URL {}", url);
+        }
+
+        Ok(HttpSink {
+            url: url.to_string(),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl Sink for HttpSink {
+    fn name(&self) -> String {
+        format!("http {}", self.url)
+    }
+
+    fn write(&mut self, data: &ClipboardData) -> Result<(), Error> {
+        let body = serde_json::to_vec(data)?;
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        let mut stream = TcpStream::connect((host, self.port))?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(10)))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.host,
+            body.len()
+        )?;
+        stream.write_all(&body)?;
+        stream.flush()?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let status = response
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| format_err!("Invalid HTTP response"))?;
+        if !(200..300).contains(&status) {
+            bail!("Server responded with status {}", status);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_http_sink_posts_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = io::BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header == "\r\n" {
+                    break;
+                }
+                if let Some(value) = header.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            (request_line, String::from_utf8(body).unwrap())
+        });
+
+        let mut sink = HttpSink::new(&format!("http://127.0.0.1:{}/clips", port)).unwrap();
+        sink.write(&ClipboardData::new(("hello".to_string(), None)))
+            .unwrap();
+        let (request_line, body) = server.join().unwrap();
+        assert_eq!(request_line, "POST /clips HTTP/1.1\r\n");
+        assert!(body.contains(r#""content":"hello""#));
+    }
+
+    #[test]
+    fn test_http_sink_rejects_https() {
+        assert!(HttpSink::new("https://example.com").is_err());
+        let sink = HttpSink::new("http://[::1]:8080/x").unwrap();
+        assert_eq!((sink.host.as_str(), sink.port), ("[::1]", 8080));
+        assert_eq!(HttpSink::new("http://[::1]/x").unwrap().port, 80);
+        assert!(HttpSink::new("http://[::1]x/").is_err());
+    }
+}
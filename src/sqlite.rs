@@ -0,0 +1,403 @@
+use crate::classify::Kind;
+use crate::common::{get_created_timestamp, ClipboardData};
+use crate::dedup::DedupMode;
+use crate::pipeline::Sink;
+use crate::retention::{Pruned, Retention};
+use crate::store::{create_private_dir, private_options};
+use failure::{format_err, Error};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Typed columns that the history is queried by, the whole record as JSON in
+/// `data`, and a full-text index over the content that is kept in sync by
+/// triggers. The text of HTML records is indexed rather than the markup.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS clips (
+        id INTEGER PRIMARY KEY,
+        type TEXT NOT NULL,
+        owner TEXT,
+        url TEXT,
+        created_at INTEGER NOT NULL,
+        kind TEXT,
+        content_hash TEXT NOT NULL,
+        text TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS clips_created_at ON clips (created_at);
+    CREATE INDEX IF NOT EXISTS clips_content_hash ON clips (content_hash);
+    CREATE VIRTUAL TABLE IF NOT EXISTS clips_fts
+        USING fts5 (text, content = 'clips', content_rowid = 'id');
+    CREATE TRIGGER IF NOT EXISTS clips_insert AFTER INSERT ON clips BEGIN
+        INSERT INTO clips_fts (rowid, text) VALUES (new.id, new.text);
+    END;
+    CREATE TRIGGER IF NOT EXISTS clips_delete AFTER DELETE ON clips BEGIN
+        INSERT INTO clips_fts (clips_fts, rowid, text) VALUES ('delete', old.id, old.text);
+    END;
+    CREATE TRIGGER IF NOT EXISTS clips_update AFTER UPDATE OF text ON clips BEGIN
+        INSERT INTO clips_fts (clips_fts, rowid, text) VALUES ('delete', old.id, old.text);
+        INSERT INTO clips_fts (rowid, text) VALUES (new.id, new.text);
+    END;
+";
+
+/// Stores the clipboard history in a SQLite database that can be searched.
+///
+/// Unlike the file sink, repeated copies in the `merge` dedup mode update the
+/// row of the first copy in place. The retention limits are enforced after
+/// every write.
+pub struct SqliteSink {
+    path: PathBuf,
+    dedup: DedupMode,
+    retention: Retention,
+    conn: Connection,
+}
+
+impl SqliteSink {
+    /// Opens the database, creating it and its tables when needed.
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        SqliteSink::with_dedup(path, DedupMode::default())
+    }
+
+    pub fn with_dedup<P: Into<PathBuf>>(path: P, dedup: DedupMode) -> Result<Self, Error> {
+        let path = path.into();
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            create_private_dir(parent)
+                .map_err(|e| format_err!("Could not create {}: {}", parent.display(), e))?;
+        }
+        // SQLite gives its journal the permissions of the database
+        private_options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| format_err!("Could not create database {}: {}", path.display(), e))?;
+        let conn = Connection::open(&path)
+            .map_err(|e| format_err!("Could not open database {}: {}", path.display(), e))?;
+        // Lets other processes read the history while the sink writes to it
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteSink {
+            path,
+            dedup,
+            retention: Retention::default(),
+            conn,
+        })
+    }
+
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Deletes the rows that are past the retention limits at `now`, a
+    /// timestamp in seconds, in the same order as `Retention::apply`.
+    fn delete_pruned(&self, now: u64) -> Result<Pruned, Error> {
+        let retention = &self.retention;
+        let copied_at = "coalesce(json_extract(data, '$.last_copied_at'), created_at)";
+        let cutoff =
+            |age: Option<Duration>| age.map(|age| now.saturating_sub(age.as_secs()) as i64);
+        let mut pruned = Pruned::default();
+        let transaction = self.conn.unchecked_transaction()?;
+        if let Some(cutoff) = cutoff(retention.sensitive_max_age) {
+            let mut kinds = vec![Value::Integer(cutoff)];
+            kinds.extend(
+                retention
+                    .sensitive_kinds
+                    .iter()
+                    .map(|&kind| Value::Text(kind_name(kind))),
+            );
+            let placeholders = vec!["?"; kinds.len() - 1].join(", ");
+            pruned.sensitive = transaction.execute(
+                &format!(
+                    "DELETE FROM clips WHERE {} < ?
+                     AND (kind IN ({}) OR json_extract(data, '$.redactions') IS NOT NULL)",
+                    copied_at, placeholders
+                ),
+                params_from_iter(kinds),
+            )?;
+        }
+        if let Some(cutoff) = cutoff(retention.max_age) {
+            pruned.expired = transaction.execute(
+                &format!("DELETE FROM clips WHERE {} < ?", copied_at),
+                [cutoff],
+            )?;
+        }
+        if let Some(max_entries) = retention.max_entries {
+            pruned.over_max_entries = transaction.execute(
+                "DELETE FROM clips WHERE id NOT IN (SELECT id FROM clips ORDER BY id DESC LIMIT ?)",
+                [max_entries.min(i64::MAX as usize) as i64],
+            )?;
+        }
+        if let Some(max_bytes) = retention.max_bytes {
+            // The size of a record is counted like a line of the file sink
+            pruned.over_max_bytes = transaction.execute(
+                "DELETE FROM clips WHERE id IN (
+                     SELECT id FROM (
+                         SELECT id, sum(length(CAST(data AS BLOB)) + 1) OVER (ORDER BY id DESC) AS size
+                         FROM clips
+                     ) WHERE size > ?
+                 )",
+                [max_bytes.min(i64::MAX as u64) as i64],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(pruned)
+    }
+
+    /// Finds the records, newest first, whose content contains all the words
+    /// of `text`, that were copied between `since` and `until` (timestamps in
+    /// seconds, both inclusive), whose owner contains `owner` and that are of
+    /// the given `kind`. Every condition is optional.
+    pub fn search(
+        &self,
+        text: Option<&str>,
+        since: Option<u64>,
+        until: Option<u64>,
+        owner: Option<&str>,
+        kind: Option<Kind>,
+        limit: usize,
+    ) -> Result<Vec<ClipboardData>, Error> {
+        let mut conditions = vec![];
+        let mut values = vec![];
+        if let Some(query) = text.map(fts_query).filter(|query| !query.is_empty()) {
+            conditions.push("id IN (SELECT rowid FROM clips_fts WHERE clips_fts MATCH ?)");
+            values.push(Value::Text(query));
+        }
+        if let Some(since) = since {
+            conditions.push("created_at >= ?");
+            values.push(Value::Integer(since as i64));
+        }
+        if let Some(until) = until {
+            conditions.push("created_at <= ?");
+            values.push(Value::Integer(until as i64));
+        }
+        if let Some(owner) = owner {
+            conditions.push("instr(lower(owner), lower(?)) > 0");
+            values.push(Value::Text(owner.to_string()));
+        }
+        if let Some(kind) = kind {
+            conditions.push("kind = ?");
+            values.push(Value::Text(kind_name(kind)));
+        }
+        let mut sql = "SELECT data FROM clips".to_string();
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY created_at DESC, id DESC LIMIT ?");
+        values.push(Value::Integer(limit.min(i64::MAX as usize) as i64));
+
+        let mut statement = self.conn.prepare(&sql)?;
+        let rows = statement.query_map(params_from_iter(values), |row| row.get::<_, String>(0))?;
+        rows.map(|data| Ok(serde_json::from_str(&data?)?)).collect()
+    }
+
+    fn insert(&self, data: &ClipboardData) -> Result<(), Error> {
+        let (url, text) = match data {
+            ClipboardData::Html { url, text, .. } => (url.as_deref(), text.as_str()),
+            ClipboardData::UnicodeText { content, .. } => (None, content.as_str()),
+        };
+        self.conn.execute(
+            "INSERT INTO clips (type, owner, url, created_at, kind, content_hash, text, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                data.type_name(),
+                data.owner(),
+                url,
+                data.created_at() as i64,
+                data.meta().kind.map(kind_name),
+                data.meta().content_hash,
+                text,
+                serde_json::to_string(data)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Bumps the `copy_count` of the first copy of the content. Returns
+    /// `false` when the content was not copied before.
+    fn merge(&self, data: &ClipboardData) -> Result<bool, Error> {
+        let first = self
+            .conn
+            .query_row(
+                "SELECT id, data FROM clips WHERE content_hash = ? ORDER BY id LIMIT 1",
+                [&data.meta().content_hash],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?;
+        let (id, first) = match first {
+            Some(first) => first,
+            None => return Ok(false),
+        };
+        let mut first: ClipboardData = serde_json::from_str(&first)?;
+        let meta = first.meta_mut();
+        meta.copy_count += 1;
+        meta.last_copied_at = Some(data.created_at());
+        self.conn.execute(
+            "UPDATE clips SET data = ? WHERE id = ?",
+            params![serde_json::to_string(&first)?, id],
+        )?;
+        Ok(true)
+    }
+
+    fn last_hash(&self) -> Result<Option<String>, Error> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT content_hash FROM clips ORDER BY id DESC LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+}
+
+impl Sink for SqliteSink {
+    fn name(&self) -> String {
+        format!("sqlite {}", self.path.display())
+    }
+
+    fn write(&mut self, data: &ClipboardData) -> Result<(), Error> {
+        let mut data = data.clone();
+        if data.meta().content_hash.is_empty() {
+            data.refresh_hash();
+        }
+        let merged = match self.dedup {
+            DedupMode::Off => false,
+            DedupMode::Merge => self.merge(&data)?,
+            DedupMode::SkipConsecutive => {
+                if self.last_hash()?.as_deref() == Some(data.meta().content_hash.as_str()) {
+                    return Ok(());
+                }
+                false
+            }
+        };
+        if !merged {
+            self.insert(&data)?;
+        }
+        if !self.retention.is_empty() {
+            self.delete_pruned(get_created_timestamp())?;
+        }
+        Ok(())
+    }
+
+    fn prune(&mut self) -> Result<Option<Pruned>, Error> {
+        self.delete_pruned(get_created_timestamp()).map(Some)
+    }
+}
+
+/// Name of the kind as it is written in the `kind` field.
+fn kind_name(kind: Kind) -> String {
+    match serde_json::to_value(kind) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => unreachable!("kinds are serialized as strings"),
+    }
+}
+
+/// Quotes every word of the search text so that characters such as `-` or `:`
+/// are not read as FTS5 operators. The words must all be present.
+fn fts_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copy(content: &str, owner: &str, created_at: u64) -> ClipboardData {
+        let mut data = ClipboardData::new((content.to_string(), Some(owner.to_string())));
+        if let ClipboardData::UnicodeText { created_at: at, .. } = &mut data {
+            *at = created_at;
+        }
+        data
+    }
+
+    #[test]
+    fn test_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = SqliteSink::open(dir.path().join("clipboard.db")).unwrap();
+        sink.write(&copy("cargo build --release", "Terminal", 100))
+            .unwrap();
+        sink.write(&copy("https://docs.rs/rusqlite", "Firefox", 200))
+            .unwrap();
+        sink.write(&copy("Release notes for the build", "Firefox", 300))
+            .unwrap();
+
+        let found = sink.search(Some("build release"), None, None, None, None, 10);
+        let found = found.unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].content(), "Release notes for the build");
+
+        let found = sink
+            .search(Some("--release"), None, Some(200), Some("term"), None, 10)
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].content(), "cargo build --release");
+
+        let found = sink
+            .search(None, Some(150), None, None, Some(Kind::Url), 10)
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].owner(), Some("Firefox"));
+        assert_eq!(
+            sink.search(None, None, None, None, None, 1).unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_merge_updates_first_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = SqliteSink::open(dir.path().join("clipboard.db")).unwrap();
+        sink.write(&copy("hello", "Editor", 100)).unwrap();
+        sink.write(&copy("world", "Editor", 200)).unwrap();
+        sink.write(&copy("hello", "Editor", 300)).unwrap();
+
+        let found = sink.search(Some("hello"), None, None, None, None, 10);
+        let found = found.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].meta().copy_count, 2);
+        assert_eq!(found[0].meta().last_copied_at, Some(300));
+        assert_eq!(found[0].created_at(), 100);
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = SqliteSink::open(dir.path().join("clipboard.db")).unwrap();
+        let now = get_created_timestamp();
+        sink.write(&copy("someone@example.com", "Mail", now - 7200))
+            .unwrap();
+        sink.write(&copy("old", "Editor", now - 3 * 86400)).unwrap();
+        for i in 0..4 {
+            sink.write(&copy(&format!("copy {}", i), "Editor", now))
+                .unwrap();
+        }
+
+        sink = sink.with_retention(Retention {
+            max_entries: Some(3),
+            max_age: Some(Duration::from_secs(86400)),
+            sensitive_kinds: vec![Kind::Email],
+            sensitive_max_age: Some(Duration::from_secs(3600)),
+            ..Retention::default()
+        });
+        let pruned = sink.prune().unwrap().unwrap();
+        assert_eq!(
+            (pruned.sensitive, pruned.expired, pruned.over_max_entries),
+            (1, 1, 1)
+        );
+        let found = sink.search(None, None, None, None, None, 10).unwrap();
+        assert_eq!(found.len(), 3);
+        assert!(sink
+            .search(Some("old"), None, None, None, None, 10)
+            .unwrap()
+            .is_empty());
+    }
+}
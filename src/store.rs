@@ -0,0 +1,1375 @@
+use crate::blobs::BlobStore;
+use crate::common::{get_created_timestamp, ClipboardData};
+use crate::crypto::{is_sealed, Cipher, Sealed};
+use crate::retention::{Pruned, Retention};
+use crate::segments;
+use crate::segments::{list_segments, open_segment, rewrite_segment, sync_dir, Rollover};
+use chrono::Utc;
+use failure::{bail, format_err, Error};
+use fs2::FileExt;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// Advisory lock that serialises the access of several processes, such as
+/// the watcher and a query, to a history.
+///
+/// The lock is taken on a `<path>.lock` file next to the history rather than
+/// on the history itself, since the history is replaced by a new file when it
+/// is rewritten. Writers hold an exclusive lock and readers a shared one. It
+/// is released when the lock is dropped. The lock file holds a generation
+/// that is bumped whenever the history is rewritten, which tells other
+/// processes that their index is stale.
+///
+/// The records are encrypted and decrypted with the cipher of the lock, if
+/// any.
+#[derive(Debug)]
+pub struct HistoryLock {
+    file: File,
+    path: PathBuf,
+    cipher: Option<Cipher>,
+}
+
+impl HistoryLock {
+    /// Waits until no writer holds the lock.
+    pub fn shared<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let lock = HistoryLock::open(path.as_ref())?;
+        lock.file.lock_shared()?;
+        Ok(lock)
+    }
+
+    /// Waits until no other reader or writer holds the lock.
+    pub fn exclusive<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let lock = HistoryLock::open(path.as_ref())?;
+        lock.file.lock_exclusive()?;
+        Ok(lock)
+    }
+
+    fn open(path: &Path) -> Result<Self, Error> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            create_private_dir(parent)
+                .map_err(|e| format_err!("Could not create {}: {}", parent.display(), e))?;
+        }
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let file = private_options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .map_err(|e| format_err!("Could not lock {}: {}", path.display(), e))?;
+        for file in [path, Path::new(&lock_path)] {
+            restrict_permissions(file, 0o600)
+                .map_err(|e| format_err!("Could not protect {}: {}", file.display(), e))?;
+        }
+        Ok(HistoryLock {
+            file,
+            path: path.to_path_buf(),
+            cipher: None,
+        })
+    }
+
+    pub fn with_cipher(mut self, cipher: Option<Cipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Number of times the history was rewritten.
+    pub fn generation(&self) -> Result<u64, Error> {
+        let mut file = &self.file;
+        let mut text = String::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_string(&mut text)?;
+        Ok(text.trim().parse().unwrap_or_default())
+    }
+
+    /// Records that the history was rewritten. The lock has to be exclusive.
+    fn bump_generation(&self) -> Result<(), Error> {
+        let generation = self.generation()? + 1;
+        let mut file = &self.file;
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "{}", generation)?;
+        Ok(())
+    }
+
+    fn reader<R: Read>(&self, reader: R, path: &Path) -> HistoryReader<R> {
+        HistoryReader {
+            cipher: self.cipher.clone(),
+            ..HistoryReader::new(reader).with_path(path)
+        }
+    }
+
+    /// Reads the records of the live history, which is empty when missing.
+    fn live_records(&self) -> Result<Vec<ClipboardData>, Error> {
+        match File::open(&self.path) {
+            Ok(file) => self.reader(file, &self.path).collect(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Closes the live history into a compressed segment when the rollover
+    /// is due at `now`, a timestamp in seconds. The lock has to be exclusive.
+    pub fn roll_over(&self, rollover: &Rollover, now: u64) -> Result<Option<PathBuf>, Error> {
+        if rollover.is_empty() {
+            return Ok(None);
+        }
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let size = file.metadata()?.len();
+        let first = match self.reader(file, &self.path).next() {
+            Some(data) => data?.created_at(),
+            None => return Ok(None),
+        };
+        if !rollover.is_due(size, first, now) {
+            return Ok(None);
+        }
+        let segment = segments::roll_over(&self.path, rollover.compression)?;
+        self.bump_generation()?;
+        Ok(Some(segment))
+    }
+
+    /// Appends a record as a single line and waits until it is on the disk.
+    /// The lock has to be exclusive.
+    pub fn append(&self, data: &ClipboardData) -> Result<(), Error> {
+        let mut line = encode_record(data, self.cipher.as_ref())?;
+        line.push(b'\n');
+        let mut file = private_options()
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Converts a history stored as a JSON array to JSON Lines, and encrypts
+    /// a history stored in plain text when the lock has a cipher. The lock
+    /// has to be exclusive.
+    pub fn migrate(&self) -> Result<bool, Error> {
+        let path = &self.path;
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        // The start of the first record tells the formats apart
+        let mut start = vec![];
+        for byte in BufReader::new(file).bytes() {
+            let byte = byte?;
+            if !start.is_empty() || !byte.is_ascii_whitespace() {
+                start.push(byte);
+            }
+            if start.len() == 16 || (byte == b'\n' && !start.is_empty()) {
+                break;
+            }
+        }
+
+        let history: Vec<ClipboardData> = if start.starts_with(b"[") {
+            serde_json::from_reader(BufReader::new(File::open(path)?)).map_err(|e| {
+                DamagedRecord {
+                    path: Some(path.clone()),
+                    line: e.line(),
+                    reason: e.to_string(),
+                }
+            })?
+        } else if self.cipher.is_some() && self.has_lines(|line| !is_sealed(line))? {
+            // Plain text records can follow encrypted ones, e.g. when they
+            // were appended without the passphrase by an older version
+            self.reader(File::open(path)?, path)
+                .collect::<Result<_, _>>()?
+        } else {
+            return Ok(false);
+        };
+        write_records(path, &history, self.cipher.as_ref())?;
+        self.bump_generation()?;
+        Ok(true)
+    }
+
+    /// Whether any line of the live history matches.
+    fn has_lines<F: Fn(&str) -> bool>(&self, matches: F) -> Result<bool, Error> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        for line in BufReader::new(file).split(b'\n') {
+            let line = line?;
+            let line = String::from_utf8_lossy(&line);
+            if !line.trim().is_empty() && matches(&line) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Fails when the history holds encrypted records and the lock has no
+    /// cipher, which would otherwise be lost or mixed with plain text.
+    fn check_cipher(&self) -> Result<(), Error> {
+        if self.cipher.is_none() && self.has_lines(is_sealed)? {
+            bail!("The history is encrypted and no passphrase was given");
+        }
+        Ok(())
+    }
+
+    /// Moves the damaged files of the history aside, the segments as well as
+    /// the live history, and writes back the records that can still be read
+    /// from them. Files that can be read in full are left alone. The lock has
+    /// to be exclusive.
+    pub fn recover(&self) -> Result<Vec<Recovery>, Error> {
+        self.check_cipher()?;
+        let mut files = list_segments(&self.path)?;
+        files.push(self.path.clone());
+        let mut recoveries = vec![];
+        for path in files {
+            let live = path == self.path;
+            let (bytes, truncated) = match read_salvageable(&path, live)? {
+                Some(read) => read,
+                None => continue,
+            };
+            let text = String::from_utf8_lossy(&bytes);
+            let (records, lost) = if text.trim_start().starts_with('[') {
+                salvage_array(&text)
+            } else {
+                salvage_lines(&text, self.cipher.as_ref())
+            };
+            let is_array = live && text.trim_start().starts_with('[');
+            if !truncated && lost == 0 && !is_array && std::str::from_utf8(&bytes).is_ok() {
+                continue;
+            }
+
+            let backup = backup_path(&path);
+            fs::rename(&path, &backup)
+                .map_err(|e| format_err!("Could not move {} aside: {}", path.display(), e))?;
+            if live {
+                write_records(&path, &records, self.cipher.as_ref())?;
+            } else if !records.is_empty() {
+                rewrite_segment(&path, |writer| {
+                    write_lines(writer, &records, self.cipher.as_ref())
+                })?;
+            }
+            recoveries.push(Recovery {
+                path,
+                backup,
+                salvaged: records.len(),
+                lost,
+            });
+        }
+        if !recoveries.is_empty() {
+            self.bump_generation()?;
+        }
+        Ok(recoveries)
+    }
+
+    /// Drops the entries that are past the retention limits at `now`, a
+    /// timestamp in seconds, from the segments and the live history. Only
+    /// the files holding the records of dropped entries are rewritten, and
+    /// segments left empty are removed. The payloads of the blob store that
+    /// no record refers to anymore are removed after the files were
+    /// rewritten. The lock has to be exclusive.
+    pub fn prune(&self, retention: &Retention, now: u64) -> Result<Pruned, Error> {
+        // Every record is tied to the entry that it was merged into
+        let mut merger = Merger::default();
+        let mut files = vec![];
+        for segment in list_segments(&self.path)? {
+            let records = self
+                .reader(open_segment(&segment)?, &segment)
+                .collect::<Result<Vec<_>, _>>()?;
+            files.push((Some(segment), records));
+        }
+        files.push((None, self.live_records()?));
+        let files = files
+            .into_iter()
+            .map(|(segment, records)| {
+                let records = records
+                    .into_iter()
+                    .map(|data| (merger.add(data.clone()), data))
+                    .collect::<Vec<_>>();
+                (segment, records)
+            })
+            .collect::<Vec<_>>();
+
+        let (keep, pruned) = retention.select(&merger.history, now);
+        let mut kept = vec![];
+        for (segment, records) in files {
+            let rewrite = !records.iter().all(|&(entry, _)| keep[entry]);
+            let records = records
+                .into_iter()
+                .filter(|&(entry, _)| keep[entry])
+                .map(|(_, data)| data)
+                .collect::<Vec<_>>();
+            if rewrite {
+                match &segment {
+                    Some(segment) if records.is_empty() => fs::remove_file(segment)?,
+                    Some(segment) => rewrite_segment(segment, |writer| {
+                        write_lines(writer, &records, self.cipher.as_ref())
+                    })?,
+                    None => write_records(&self.path, &records, self.cipher.as_ref())?,
+                }
+            }
+            kept.extend(records);
+        }
+        if pruned.total() > 0 {
+            self.bump_generation()?;
+            // Only once every file is rewritten, so that a failed rewrite
+            // never leaves records whose payloads are gone
+            BlobStore::new(&self.path).collect_garbage(&kept)?;
+        }
+        Ok(pruned)
+    }
+
+    /// Rewrites every record of the segments and the live history with the
+    /// new cipher, or in plain text when there is none, after reading them
+    /// with the cipher of the lock. Returns the number of records. The lock
+    /// has to be exclusive.
+    pub fn rekey(&self, cipher: Option<&Cipher>) -> Result<usize, Error> {
+        let mut count = 0;
+        for segment in list_segments(&self.path)? {
+            let records = self
+                .reader(open_segment(&segment)?, &segment)
+                .collect::<Result<Vec<_>, _>>()?;
+            rewrite_segment(&segment, |writer| write_lines(writer, &records, cipher))?;
+            count += records.len();
+        }
+        if self.path.exists() {
+            let records = self.live_records()?;
+            write_records(&self.path, &records, cipher)?;
+            count += records.len();
+        }
+        self.bump_generation()?;
+        Ok(count)
+    }
+}
+
+/// Reads the records of a JSON Lines history one at a time, without loading
+/// the whole file into memory.
+///
+/// Every line is a record as it was appended, so a content that was copied
+/// several times with the `merge` dedup mode appears once per copy. Use
+/// `read_history` to get the entries with their copies merged. Encrypted
+/// records are decrypted with the cipher given to `with_cipher`.
+pub struct HistoryReader<R> {
+    lines: Option<io::Lines<BufReader<R>>>,
+    /// Files that are read once the current one is done, and how to open
+    /// them
+    pending: VecDeque<PathBuf>,
+    open: fn(&Path, bool) -> Result<Option<R>, Error>,
+    /// File that is being read, if known, and the line in it
+    path: Option<PathBuf>,
+    line: usize,
+    cipher: Option<Cipher>,
+    /// Keeps writers out while the history is being read
+    _lock: Option<HistoryLock>,
+}
+
+impl HistoryReader<Box<dyn Read + Send>> {
+    /// Opens the history, holding a shared lock on it until the reader is
+    /// dropped. The records of the closed segments are read first, oldest
+    /// segment first, and then those of the live history. Every file is
+    /// opened only once the previous one is read.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let lock = HistoryLock::shared(path)?;
+        let mut pending = VecDeque::from(list_segments(path)?);
+        if pending.is_empty() {
+            File::open(path)
+                .map_err(|e| format_err!("Could not open {}: {}", path.display(), e))?;
+        }
+        pending.push_back(path.to_path_buf());
+        Ok(HistoryReader {
+            lines: None,
+            pending,
+            // Every record is in the segments right after a rollover
+            open: |path, live| match File::open(path) {
+                Ok(file) if live => Ok(Some(Box::new(file))),
+                Ok(_) => open_segment(path).map(Some),
+                Err(ref e) if live && e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            _lock: Some(lock),
+            ..HistoryReader::new(Box::new(io::empty()))
+        })
+    }
+}
+
+impl<R: Read> HistoryReader<R> {
+    pub fn new(reader: R) -> Self {
+        HistoryReader {
+            lines: Some(BufReader::new(reader).lines()),
+            pending: VecDeque::new(),
+            open: |_, _| Ok(None),
+            path: None,
+            line: 0,
+            cipher: None,
+            _lock: None,
+        }
+    }
+
+    pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Names the file that is read in the errors about damaged records.
+    pub fn with_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    fn damaged(&self, reason: String) -> Error {
+        DamagedRecord {
+            path: self.path.clone(),
+            line: self.line,
+            reason,
+        }
+        .into()
+    }
+}
+
+impl<R: Read> Iterator for HistoryReader<R> {
+    type Item = Result<ClipboardData, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.as_mut().and_then(Iterator::next) {
+                Some(Ok(line)) => line,
+                // A truncated segment or a line that is not UTF-8
+                Some(Err(e))
+                    if e.kind() == io::ErrorKind::InvalidData
+                        || e.kind() == io::ErrorKind::UnexpectedEof =>
+                {
+                    self.lines = None;
+                    self.pending.clear();
+                    self.line += 1;
+                    return Some(Err(self.damaged(e.to_string())));
+                }
+                Some(Err(e)) => return Some(Err(e.into())),
+                None => {
+                    let path = self.pending.pop_front()?;
+                    // The live history comes last
+                    let live = self.pending.is_empty();
+                    self.lines = match (self.open)(&path, live) {
+                        Ok(reader) => reader.map(|reader| BufReader::new(reader).lines()),
+                        Err(e) => return Some(Err(e)),
+                    };
+                    self.path = Some(path);
+                    self.line = 0;
+                    continue;
+                }
+            };
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let data = decode_record(&line, self.cipher.as_ref()).map_err(|e| {
+                match e.downcast::<serde_json::Error>() {
+                    Ok(e) => self.damaged(e.to_string()),
+                    Err(e) => e,
+                }
+            });
+            // Records written before the hash existed get one on the fly
+            return Some(data.map(|mut data| {
+                if data.meta().content_hash.is_empty() {
+                    data.refresh_hash();
+                }
+                data
+            }));
+        }
+    }
+}
+
+/// Reads the whole history, merging the records of repeated copies into the
+/// entry of the first copy like the JSON array format used to store them.
+pub fn read_history<P: AsRef<Path>>(path: P) -> Result<Vec<ClipboardData>, Error> {
+    merge_copies(HistoryReader::open(path)?)
+}
+
+/// Reads the whole history like `read_history`, decrypting the records.
+pub fn read_encrypted_history<P: AsRef<Path>>(
+    path: P,
+    cipher: &Cipher,
+) -> Result<Vec<ClipboardData>, Error> {
+    merge_copies(HistoryReader::open(path)?.with_cipher(cipher.clone()))
+}
+
+fn merge_copies<R: Read>(reader: HistoryReader<R>) -> Result<Vec<ClipboardData>, Error> {
+    let mut merger = Merger::default();
+    for data in reader {
+        merger.add(data?);
+    }
+    Ok(merger.history)
+}
+
+/// Builds the entries of the history from its records.
+#[derive(Default)]
+struct Merger {
+    history: Vec<ClipboardData>,
+    /// Latest entry of every content hash
+    entries: HashMap<String, usize>,
+}
+
+impl Merger {
+    /// Adds a record and returns the entry that it was merged into.
+    fn add(&mut self, data: ClipboardData) -> usize {
+        let hash = data.meta().content_hash.clone();
+        match self.entries.get(&hash) {
+            Some(&i) if data.meta().copy_count > 1 => {
+                let meta = self.history[i].meta_mut();
+                meta.copy_count = data.meta().copy_count;
+                meta.last_copied_at = data.meta().last_copied_at;
+                i
+            }
+            _ => {
+                self.entries.insert(hash, self.history.len());
+                self.history.push(data);
+                self.history.len() - 1
+            }
+        }
+    }
+}
+
+/// Appends a record to the history as a single line, encrypted with the
+/// cipher if any. Fails without a cipher when the history is encrypted.
+pub fn append_record<P: AsRef<Path>>(
+    path: P,
+    data: &ClipboardData,
+    cipher: Option<&Cipher>,
+) -> Result<(), Error> {
+    let lock = HistoryLock::exclusive(path)?.with_cipher(cipher.cloned());
+    // A history in plain text is encrypted before the record is appended
+    lock.check_cipher()?;
+    lock.migrate()?;
+    lock.append(data)
+}
+
+/// Converts a history stored as a JSON array, as written by older versions,
+/// to JSON Lines. Returns false when the file is missing or already uses
+/// JSON Lines.
+pub fn migrate<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
+    HistoryLock::exclusive(path)?.migrate()
+}
+
+/// Drops the entries of the history that are past the retention limits.
+pub fn prune<P: AsRef<Path>>(
+    path: P,
+    retention: &Retention,
+    cipher: Option<&Cipher>,
+) -> Result<Pruned, Error> {
+    HistoryLock::exclusive(path)?
+        .with_cipher(cipher.cloned())
+        .prune(retention, get_created_timestamp())
+}
+
+/// Re-encrypts the history with a new cipher. A history in plain text is
+/// read without an `old` cipher, and is written in plain text without a
+/// `new` one.
+pub fn rekey<P: AsRef<Path>>(
+    path: P,
+    old: Option<&Cipher>,
+    new: Option<&Cipher>,
+) -> Result<usize, Error> {
+    HistoryLock::exclusive(path)?
+        .with_cipher(old.cloned())
+        .rekey(new)
+}
+
+/// Serializes the record as a line of the history, encrypted when there is a
+/// cipher.
+fn encode_record(data: &ClipboardData, cipher: Option<&Cipher>) -> Result<Vec<u8>, Error> {
+    let json = Zeroizing::new(serde_json::to_vec(data)?);
+    match cipher {
+        Some(cipher) => Ok(serde_json::to_vec(&cipher.seal(&json)?)?),
+        None => Ok(json.to_vec()),
+    }
+}
+
+/// Parses a line of the history. Lines that are not valid JSON fail with a
+/// `serde_json::Error`.
+fn decode_record(line: &str, cipher: Option<&Cipher>) -> Result<ClipboardData, Error> {
+    if !is_sealed(line) {
+        return Ok(serde_json::from_str(line)?);
+    }
+    let sealed: Sealed = serde_json::from_str(line)?;
+    match cipher {
+        Some(cipher) => Ok(serde_json::from_slice(&cipher.open(&sealed)?)?),
+        None => bail!("The history is encrypted and no passphrase was given"),
+    }
+}
+
+/// Replaces the file with the records. They are written to a temporary file
+/// which is flushed to the disk and then renamed over the file, so that a
+/// crash at any point leaves either the old or the new file in place.
+fn write_records(
+    path: &Path,
+    records: &[ClipboardData],
+    cipher: Option<&Cipher>,
+) -> Result<(), Error> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let file = private_options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&temp)?;
+    let mut writer = BufWriter::new(file);
+    write_lines(&mut writer, records, cipher)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(&temp, path)?;
+    // The rename itself is only durable once the directory is synced
+    sync_dir(path)
+}
+
+fn write_lines(
+    writer: &mut dyn Write,
+    records: &[ClipboardData],
+    cipher: Option<&Cipher>,
+) -> io::Result<()> {
+    for data in records {
+        let line = encode_record(data, cipher).map_err(|e| io::Error::other(e.to_string()))?;
+        writer.write_all(&line)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Options that create files only the user can read and write, since the
+/// history holds everything that was copied.
+pub(crate) fn private_options() -> OpenOptions {
+    #[allow(unused_mut)]
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+}
+
+/// Creates the directory and its missing parents so that only the user can
+/// access them. Existing directories are left as they are.
+pub(crate) fn create_private_dir(path: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(path)
+}
+
+/// Takes away the permissions of the file or directory that go beyond
+/// `mode`, such as those of a history created by an older version. Missing
+/// paths are ignored.
+pub(crate) fn restrict_permissions(path: &Path, mode: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if extra_permissions(path, mode)? != 0 {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
+/// Permissions of the file or directory that go beyond `mode`. There are
+/// none for missing paths, and on other systems than Unix.
+pub(crate) fn extra_permissions(path: &Path, mode: u32) -> io::Result<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        match fs::metadata(path) {
+            Ok(metadata) => Ok(metadata.permissions().mode() & 0o777 & !mode),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, mode);
+        Ok(0)
+    }
+}
+
+/// A line of the history that is not a valid record, a segment that cannot
+/// be decompressed, or a JSON array history that cannot be parsed.
+#[derive(Debug)]
+pub struct DamagedRecord {
+    /// File of the history that is damaged, a segment or the live history
+    pub path: Option<PathBuf>,
+    /// Line in that file
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for DamagedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(
+                f,
+                "Invalid record on line {} of {}: {}",
+                self.line,
+                path.display(),
+                self.reason
+            ),
+            None => write!(f, "Invalid record on line {}: {}", self.line, self.reason),
+        }
+    }
+}
+
+impl std::error::Error for DamagedRecord {}
+
+/// Outcome of recovering a damaged file of a history.
+#[derive(Debug)]
+pub struct Recovery {
+    /// The damaged file, a segment or the live history
+    pub path: PathBuf,
+    /// Where the damaged file was moved to
+    pub backup: PathBuf,
+    /// Number of records that were read from the damaged file
+    pub salvaged: usize,
+    /// Number of records or lines that could not be read
+    pub lost: usize,
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} records of {} were salvaged and {} could not be read. The damaged file was kept as {}",
+            self.salvaged,
+            self.path.display(),
+            self.lost,
+            self.backup.display()
+        )
+    }
+}
+
+/// Moves the damaged files of a history, segments or the live history, aside
+/// to timestamped backups and writes the records that can still be read back
+/// to them. The backups are never overwritten or removed. An encrypted
+/// history needs its cipher. Returns one recovery per damaged file.
+pub fn recover<P: AsRef<Path>>(path: P, cipher: Option<&Cipher>) -> Result<Vec<Recovery>, Error> {
+    HistoryLock::exclusive(path)?
+        .with_cipher(cipher.cloned())
+        .recover()
+}
+
+/// Free `<path>.corrupt-<timestamp>` name for the backup of a damaged file.
+fn backup_path(path: &Path) -> PathBuf {
+    // In UTC like the names of the segments
+    let stamp = Utc::now().format("%Y%m%dT%H%M%S");
+    let mut backup = PathBuf::from(format!("{}.corrupt-{}", path.display(), stamp));
+    let mut attempt = 1;
+    while backup.exists() {
+        attempt += 1;
+        backup = PathBuf::from(format!("{}.corrupt-{}-{}", path.display(), stamp, attempt));
+    }
+    backup
+}
+
+/// Reads a file of the history as far as it can be read. Returns `None` for
+/// a missing file, and otherwise the data and whether it ended early, like
+/// a truncated segment.
+fn read_salvageable(path: &Path, live: bool) -> Result<Option<(Vec<u8>, bool)>, Error> {
+    let mut reader = match File::open(path) {
+        Ok(file) if live => Box::new(file),
+        Ok(_) => open_segment(path)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut bytes = vec![];
+    let mut buf = [0; 8192];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(Some((bytes, false))),
+            Ok(read) => bytes.extend_from_slice(&buf[..read]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(ref e)
+                if e.kind() == io::ErrorKind::InvalidData
+                    || e.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                return Ok(Some((bytes, true)))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn salvage_lines(text: &str, cipher: Option<&Cipher>) -> (Vec<ClipboardData>, usize) {
+    let mut records = vec![];
+    let mut lost = 0;
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        match decode_record(line, cipher) {
+            Ok(data) => records.push(data),
+            Err(_) => lost += 1,
+        }
+    }
+    (records, lost)
+}
+
+/// Parses every object at the top level of a JSON array on its own, so that
+/// a damaged object or a truncated end only loses the affected records.
+fn salvage_array(text: &str) -> (Vec<ClipboardData>, usize) {
+    let mut records = vec![];
+    let mut lost = 0;
+    let mut depth = 0;
+    let mut start = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '[' | '{' => {
+                depth += 1;
+                if depth == 2 && c == '{' {
+                    start = Some(i);
+                }
+            }
+            ']' | '}' => {
+                if depth == 2 {
+                    if let Some(start) = start.take() {
+                        match serde_json::from_str(&text[start..=i]) {
+                            Ok(data) => records.push(data),
+                            Err(_) => lost += 1,
+                        }
+                    }
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    if start.is_some() {
+        lost += 1;
+    }
+    (records, lost)
+}
+
+/// What the history already holds, which lets the dedup modes work without
+/// reading the file again for every copy.
+#[derive(Debug, Default)]
+pub struct HistoryIndex {
+    /// Copies of every content hash
+    copies: HashMap<String, Copies>,
+    last_hash: Option<String>,
+    /// Whether the closed segments were read
+    segments_read: bool,
+    /// Size of the live history that the index covers
+    size: u64,
+    /// Generation of the history in the lock file
+    generation: u64,
+    /// Number of entries once the copies are merged
+    entries: usize,
+    /// Size of the entries as `Retention` counts it
+    bytes: u64,
+    /// Inode of the history, which changes when it is rewritten
+    #[cfg(unix)]
+    inode: u64,
+}
+
+impl HistoryIndex {
+    /// Builds the index by streaming the history that the lock is held on. A
+    /// missing file is an empty history.
+    pub fn load(lock: &HistoryLock) -> Result<Self, Error> {
+        let mut index = HistoryIndex::default();
+        index.update(lock)?;
+        Ok(index)
+    }
+
+    /// Reads the records that other processes appended since the index was
+    /// last updated. The index is rebuilt, including the closed segments,
+    /// when the history was rewritten or rolled over, which is told by the
+    /// generation in the lock file, or for rewrites by other programs by the
+    /// live history getting smaller or, on Unix, by its inode changing.
+    pub fn update(&mut self, lock: &HistoryLock) -> Result<(), Error> {
+        let file = match File::open(&lock.path) {
+            Ok(file) => Some(file),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let metadata = file.as_ref().map(File::metadata).transpose()?;
+        let size = metadata.as_ref().map_or(0, |metadata| metadata.len());
+        let generation = lock.generation()?;
+        #[allow(unused_mut)]
+        let mut rewritten = size < self.size || generation != self.generation;
+        // A history that was rewritten by another process can be larger
+        #[cfg(unix)]
+        let inode = {
+            use std::os::unix::fs::MetadataExt;
+            let inode = metadata.as_ref().map_or(0, |metadata| metadata.ino());
+            rewritten |= self.size > 0 && inode != self.inode;
+            inode
+        };
+        if rewritten {
+            *self = HistoryIndex::default();
+        }
+        self.generation = generation;
+        #[cfg(unix)]
+        {
+            self.inode = inode;
+        }
+
+        if !self.segments_read {
+            for segment in list_segments(&lock.path)? {
+                for data in lock.reader(open_segment(&segment)?, &segment) {
+                    self.add(&data?);
+                }
+            }
+            self.segments_read = true;
+        }
+        let mut file = match file {
+            Some(file) if size != self.size => file,
+            _ => return Ok(()),
+        };
+        file.seek(SeekFrom::Start(self.size))?;
+        for data in lock.reader(file, &lock.path) {
+            self.add(&data?);
+        }
+        self.size = size;
+        Ok(())
+    }
+
+    /// Starts covering a new live history after the previous one was closed
+    /// into a segment, whose records the index already covers.
+    pub fn rolled_over(&mut self, lock: &HistoryLock) -> Result<(), Error> {
+        self.size = 0;
+        self.generation = lock.generation()?;
+        #[cfg(unix)]
+        {
+            self.inode = 0;
+        }
+        Ok(())
+    }
+
+    /// Number of times the content was copied so far.
+    pub fn copies(&self, hash: &str) -> u32 {
+        self.copies.get(hash).map_or(0, |copies| copies.max)
+    }
+
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Whether the index does not cover any record of the live history yet.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn last_hash(&self) -> Option<&str> {
+        self.last_hash.as_deref()
+    }
+
+    fn add(&mut self, data: &ClipboardData) {
+        let hash = data.meta().content_hash.clone();
+        let (count, last_copied_at) = (data.meta().copy_count, data.meta().last_copied_at);
+        // Same as `read_history` merging the copies
+        match self.copies.get_mut(&hash) {
+            Some(copies) if count > 1 => {
+                // Only the copy count and time of the entry change
+                self.bytes += merged_size(count, last_copied_at);
+                self.bytes -= merged_size(copies.count, copies.last_copied_at);
+                copies.max = copies.max.max(count);
+                copies.count = count;
+                copies.last_copied_at = last_copied_at;
+            }
+            copies => {
+                let max = copies.map_or(0, |copies| copies.max).max(count);
+                self.entries += 1;
+                self.bytes += serde_json::to_vec(data).map_or(0, |json| json.len() as u64 + 1);
+                self.copies.insert(
+                    hash.clone(),
+                    Copies {
+                        max,
+                        count,
+                        last_copied_at,
+                    },
+                );
+            }
+        }
+        self.last_hash = Some(hash);
+    }
+}
+
+/// What the index knows about the records of a content hash.
+#[derive(Debug, Default)]
+struct Copies {
+    /// Highest copy count of the records
+    max: u32,
+    /// Copy count and time of the entry that the next copy is merged into
+    count: u32,
+    last_copied_at: Option<u64>,
+}
+
+/// Size that the copy count and time take in a serialized record.
+fn merged_size(count: u32, last_copied_at: Option<u64>) -> u64 {
+    let size = |number: u64| number.to_string().len() as u64;
+    size(count.into())
+        + last_copied_at.map_or(0, |time| r#","last_copied_at":"#.len() as u64 + size(time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dedup::DedupMode;
+    use crate::pipeline::Sink;
+    use crate::segments::Compression;
+    use crate::sinks::FileSink;
+
+    fn text(content: &str) -> ClipboardData {
+        ClipboardData::new((content.to_string(), None))
+    }
+
+    #[test]
+    fn test_append_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        let mut sink = FileSink::with_dedup(&path, DedupMode::Merge);
+        for content in &["a", "b", "a", "a"] {
+            sink.write(&text(content)).unwrap();
+        }
+
+        let lines = HistoryReader::open(&path).unwrap().count();
+        assert_eq!(lines, 4);
+        let history = read_history(&path).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].content(), "a");
+        assert_eq!(history[0].meta().copy_count, 3);
+        assert!(history[0].meta().last_copied_at.is_some());
+
+        // A new sink picks up the counts from the file
+        let mut sink = FileSink::with_dedup(&path, DedupMode::Merge);
+        sink.write(&text("b")).unwrap();
+        assert_eq!(read_history(&path).unwrap()[1].meta().copy_count, 2);
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        let writers = (0..4)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let mut sink = FileSink::with_dedup(&path, DedupMode::Merge);
+                    for _ in 0..25 {
+                        sink.write(&text("same")).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // Every writer saw the copies of the others
+        let history = read_history(&path).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].meta().copy_count, 100);
+
+        // Another process rewrites the history and appends to it
+        let mut sink = FileSink::with_dedup(&path, DedupMode::Merge);
+        sink.write(&text("same")).unwrap();
+        let lock = HistoryLock::exclusive(&path).unwrap();
+        lock.rekey(None).unwrap();
+        for content in &["a", "b", "c"] {
+            lock.append(&text(content)).unwrap();
+        }
+        assert_eq!(lock.generation().unwrap(), 1);
+        drop(lock);
+        sink.write(&text("same")).unwrap();
+        assert_eq!(read_history(&path).unwrap()[0].meta().copy_count, 102);
+    }
+
+    #[test]
+    fn test_recover_damaged_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        let record = serde_json::to_string(&text("a")).unwrap();
+        // The last append was cut short by a crash
+        fs::write(&path, format!("{}\n{}", record, &record[..10])).unwrap();
+        assert!(read_history(&path)
+            .unwrap_err()
+            .downcast_ref::<DamagedRecord>()
+            .is_some());
+
+        let mut sink = FileSink::new(&path);
+        sink.write(&text("b")).unwrap();
+        let history = read_history(&path).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].content(), "b");
+        let backups = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("clipboard.json.corrupt-"))
+            .collect::<Vec<_>>();
+        assert_eq!(backups.len(), 1);
+
+        fs::write(
+            &path,
+            format!(
+                "[{}, {{\"type\": \"text\"}}, {}, {{\"type\"",
+                record, record
+            ),
+        )
+        .unwrap();
+        let recoveries = recover(&path, None).unwrap();
+        assert_eq!(recoveries.len(), 1);
+        assert_eq!((recoveries[0].salvaged, recoveries[0].lost), (2, 2));
+        assert_eq!(read_history(&path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_recover_damaged_segment() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        let rollover = Rollover {
+            max_bytes: Some(1),
+            ..Rollover::default()
+        };
+        let mut sink = FileSink::new(&path).with_rollover(rollover.clone());
+        for content in &["a", "b", "c"] {
+            sink.write(&text(content)).unwrap();
+        }
+        let segments = list_segments(&path).unwrap();
+        assert_eq!(segments.len(), 2);
+        // The disk filled up while the first segment was compressed
+        let file = OpenOptions::new().write(true).open(&segments[0]).unwrap();
+        file.set_len(file.metadata().unwrap().len() / 2).unwrap();
+        drop(file);
+        let err = read_history(&path).unwrap_err();
+        let damage = err.downcast_ref::<DamagedRecord>().unwrap();
+        assert_eq!(damage.path.as_ref(), Some(&segments[0]));
+
+        let mut sink = FileSink::new(&path).with_rollover(rollover);
+        sink.write(&text("d")).unwrap();
+        let history = read_history(&path).unwrap();
+        let contents = history
+            .iter()
+            .map(|data| data.content())
+            .collect::<Vec<_>>();
+        assert_eq!(contents, ["b", "c", "d"]);
+        let mut backup = segments[0].clone().into_os_string();
+        backup.push(".corrupt-");
+        let backups = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path().into_os_string())
+            .filter(|name| name.to_str().unwrap().starts_with(backup.to_str().unwrap()))
+            .count();
+        assert_eq!(backups, 1);
+        assert!(recover(&path, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_retention_on_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        let retention = Retention {
+            max_entries: Some(2),
+            ..Retention::default()
+        };
+        let mut sink = FileSink::new(&path).with_retention(retention.clone());
+        for content in &["a", "b", "a", "c", "d"] {
+            sink.write(&text(content)).unwrap();
+        }
+        let history = read_history(&path).unwrap();
+        let contents = history
+            .iter()
+            .map(ClipboardData::content)
+            .collect::<Vec<_>>();
+        assert_eq!(contents, vec!["c", "d"]);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        let retention = Retention {
+            max_entries: Some(1),
+            ..retention
+        };
+        let pruned = prune(&path, &retention, None).unwrap();
+        assert_eq!(pruned.over_max_entries, 1);
+        assert_eq!(read_history(&path).unwrap()[0].content(), "d");
+
+        // The limits are enforced exactly from the counts of the index
+        let path = dir.path().join("large.json");
+        let retention = Retention {
+            max_entries: Some(20),
+            ..Retention::default()
+        };
+        let mut sink = FileSink::new(&path).with_retention(retention);
+        for i in 0..22 {
+            sink.write(&text(&i.to_string())).unwrap();
+        }
+        assert_eq!(read_history(&path).unwrap().len(), 20);
+        assert_eq!(read_history(&path).unwrap()[0].content(), "2");
+
+        // Repeated copies grow the merged entries, which counts for the size
+        let path = dir.path().join("bytes.json");
+        let mut sink = FileSink::with_dedup(&path, DedupMode::Merge);
+        for content in &["a", "b", "a", "a", "a", "a", "a", "a", "a", "a", "a"] {
+            sink.write(&text(content)).unwrap();
+        }
+        let history = read_history(&path).unwrap();
+        let size = history
+            .iter()
+            .map(|data| serde_json::to_vec(data).unwrap().len() as u64 + 1)
+            .sum::<u64>();
+        let index = HistoryIndex::load(&HistoryLock::shared(&path).unwrap()).unwrap();
+        assert_eq!((index.entries(), index.bytes()), (2, size));
+    }
+
+    #[test]
+    fn test_rollover() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        let rollover = Rollover {
+            max_bytes: Some(1),
+            compression: Compression::Zstd,
+            ..Rollover::default()
+        };
+        let mut sink = FileSink::new(&path).with_rollover(rollover);
+        for content in &["a", "b", "a"] {
+            sink.write(&text(content)).unwrap();
+        }
+        let segments = list_segments(&path).unwrap();
+        assert_eq!(segments.len(), 2);
+        assert!(segments[0].to_str().unwrap().ends_with(".zst"));
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+        // Copies are merged across segments
+        let history = read_history(&path).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].meta().copy_count, 2);
+
+        let retention = Retention {
+            max_entries: Some(1),
+            ..Retention::default()
+        };
+        assert_eq!(prune(&path, &retention, None).unwrap().over_max_entries, 1);
+        assert_eq!(list_segments(&path).unwrap().len(), 1);
+        assert_eq!(read_history(&path).unwrap()[0].content(), "b");
+    }
+
+    #[test]
+    fn test_encrypted_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        // A history in plain text is encrypted on the first write
+        append_record(&path, &text("hunter2 is my password"), None).unwrap();
+        let cipher = Cipher::new("correct horse").unwrap();
+        let mut sink = FileSink::new(&path).with_cipher(cipher.clone());
+        sink.write(&text("ssh root@10.0.0.1")).unwrap();
+        sink.write(&text("hunter2 is my password")).unwrap();
+
+        for entry in fs::read_dir(dir.path()).unwrap() {
+            let bytes = fs::read(entry.unwrap().path()).unwrap();
+            let bytes = String::from_utf8_lossy(&bytes);
+            for plaintext in &["hunter2", "root@10", "content_hash", "\"text\""] {
+                assert!(!bytes.contains(plaintext));
+            }
+        }
+        let history = read_encrypted_history(&path, &cipher).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].meta().copy_count, 2);
+        assert!(read_history(&path).is_err());
+        assert!(append_record(&path, &text("leak"), None).is_err());
+        assert!(recover(&path, None).is_err());
+
+        // Plain text records later in the history are encrypted as well
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "{}", serde_json::to_string(&text("late")).unwrap()).unwrap();
+        let lock = HistoryLock::exclusive(&path).unwrap();
+        assert!(lock.with_cipher(Some(cipher.clone())).migrate().unwrap());
+        assert!(!fs::read_to_string(&path).unwrap().contains("late"));
+        append_record(&path, &text("more"), Some(&cipher)).unwrap();
+        assert_eq!(read_encrypted_history(&path, &cipher).unwrap().len(), 4);
+
+        // A wrong passphrase is not mistaken for a damaged history
+        let wrong = Cipher::new("wrong").unwrap();
+        let mut sink = FileSink::new(&path).with_cipher(wrong.clone());
+        let err = sink.write(&text("more")).unwrap_err();
+        assert!(err.downcast_ref::<DamagedRecord>().is_none());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        let new = Cipher::new("battery staple").unwrap();
+        assert_eq!(rekey(&path, Some(&cipher), Some(&new)).unwrap(), 5);
+        assert!(read_encrypted_history(&path, &cipher).is_err());
+        assert_eq!(read_encrypted_history(&path, &new).unwrap().len(), 4);
+        assert_eq!(rekey(&path, Some(&new), None).unwrap(), 5);
+        assert_eq!(
+            read_history(&path).unwrap()[1].content(),
+            "ssh root@10.0.0.1"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("data").join("clipboard2json");
+        let path = data_dir.join("clipboard.json");
+        let mut sink = FileSink::new(&path).with_retention(Retention {
+            max_entries: Some(1),
+            ..Retention::default()
+        });
+        sink.write(&text("a")).unwrap();
+        sink.write(&text("b")).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&data_dir), 0o700);
+        assert_eq!(mode(data_dir.parent().unwrap()), 0o700);
+        for entry in fs::read_dir(&data_dir).unwrap() {
+            assert_eq!(mode(&entry.unwrap().path()), 0o600);
+        }
+
+        // A history created by an older version is restricted as well
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        sink.write(&text("c")).unwrap();
+        assert_eq!(mode(&path), 0o600);
+    }
+
+    #[test]
+    fn test_migrate_array() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.json");
+        fs::write(
+            &path,
+            serde_json::to_string_pretty(&vec![text("a"), text("b")]).unwrap(),
+        )
+        .unwrap();
+
+        assert!(migrate(&path).unwrap());
+        assert!(!migrate(&path).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        let history = read_history(&path).unwrap();
+        assert_eq!(history[1].content(), "b");
+    }
+}
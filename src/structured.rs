@@ -0,0 +1,219 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Data formats that copied documents are parsed from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+    Xml,
+}
+
+lazy_static! {
+    static ref JSON_OBJECT: Regex = Regex::new(r#"^\{\s*(?:"|\})"#).unwrap();
+    static ref JSON_ARRAY: Regex =
+        Regex::new(r#"^\[\s*(?:[\[{"\]]|-?\d|true\b|false\b|null\b)"#).unwrap();
+    static ref XML_ROOT: Regex = Regex::new(r"^<([A-Za-z_][\w:.-]*)[\s/>]").unwrap();
+    static ref TOML_TABLE: Regex = Regex::new(r#"^\[\[?[\w.\-" ]+\]\]?$"#).unwrap();
+    static ref TOML_KEY: Regex = Regex::new(r#"^[\w.\-"]+\s*=\s*\S"#).unwrap();
+    static ref YAML_KEY: Regex = Regex::new(r#"^\s*[\w.\-"']+:(?:\s|$)"#).unwrap();
+    static ref YAML_ITEM: Regex = Regex::new(r"^\s*- ").unwrap();
+}
+
+/// Detects whether the text is a JSON, YAML, TOML or XML document and parses
+/// it into a JSON value.
+///
+/// Returns `None` when the text does not look like any of the formats. When it
+/// does but cannot be parsed, the parse error is returned instead of the value
+/// so that a document that is nearly valid is not silently treated as text.
+pub fn parse_document(text: &str) -> Option<(Format, Result<Value, String>)> {
+    let text = text.trim();
+    let format = detect(text)?;
+    let parsed =
+        match format {
+            Format::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+            Format::Yaml => serde_yaml::from_str::<serde_yaml::Value>(text)
+                .map_err(|e| e.to_string())
+                .and_then(|value| serde_json::to_value(value).map_err(|e| e.to_string())),
+            Format::Toml => toml::from_str(text)
+                .map(toml_to_json)
+                .map_err(|e: toml::de::Error| match e.span() {
+                    Some(span) => {
+                        let line = text[..span.start].matches('\n').count() + 1;
+                        format!("{} at line {}", e.message().trim_end(), line)
+                    }
+                    None => e.message().to_string(),
+                }),
+            Format::Xml => roxmltree::Document::parse(text)
+                .map(|document| {
+                    let root = document.root_element();
+                    let mut object = Map::new();
+                    object.insert(root.tag_name().name().to_string(), xml_to_json(root));
+                    Value::Object(object)
+                })
+                .map_err(|e| e.to_string()),
+        };
+    Some((format, parsed))
+}
+
+fn detect(text: &str) -> Option<Format> {
+    if JSON_OBJECT.is_match(text) && text.ends_with('}')
+        || JSON_ARRAY.is_match(text) && text.ends_with(']')
+    {
+        return Some(Format::Json);
+    }
+    if text.starts_with("<?xml")
+        || XML_ROOT
+            .captures(text)
+            .is_some_and(|root| text.ends_with("/>") || text.ends_with(&format!("</{}>", &root[1])))
+    {
+        return Some(Format::Xml);
+    }
+
+    let lines = text
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .collect::<Vec<_>>();
+    let first = lines.first()?;
+    let count = |re: &Regex| lines.iter().filter(|line| re.is_match(line)).count();
+    if (TOML_TABLE.is_match(first) || TOML_KEY.is_match(first)) && count(&TOML_KEY) >= 1 {
+        return Some(Format::Toml);
+    }
+    let structured = count(&YAML_KEY) + count(&YAML_ITEM);
+    if (*first == "---" || YAML_KEY.is_match(first) || YAML_ITEM.is_match(first))
+        && lines.len() >= 2
+        && structured >= 2
+    {
+        return Some(Format::Yaml);
+    }
+    None
+}
+
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(string) => Value::String(string),
+        toml::Value::Integer(integer) => Value::from(integer),
+        toml::Value::Float(float) => Value::from(float),
+        toml::Value::Boolean(boolean) => Value::Bool(boolean),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(array) => Value::Array(array.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Converts an element to JSON. Attributes become `@name` keys, the text
+/// becomes `#text` and child elements are grouped by name, turning into an
+/// array when the name repeats. An element with only text is a string.
+fn xml_to_json(element: roxmltree::Node) -> Value {
+    let mut object = Map::new();
+    for attribute in element.attributes() {
+        object.insert(
+            format!("@{}", attribute.name()),
+            Value::String(attribute.value().to_string()),
+        );
+    }
+
+    let mut text = String::new();
+    for child in element.children() {
+        if child.is_text() {
+            text.push_str(child.text().unwrap_or_default());
+            continue;
+        }
+        if !child.is_element() {
+            continue;
+        }
+        let value = xml_to_json(child);
+        match object.get_mut(child.tag_name().name()) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            }
+            None => {
+                object.insert(child.tag_name().name().to_string(), value);
+            }
+        }
+    }
+
+    let text = text.trim();
+    if object.is_empty() {
+        return if text.is_empty() {
+            Value::Null
+        } else {
+            Value::String(text.to_string())
+        };
+    }
+    if !text.is_empty() {
+        object.insert("#text".to_string(), Value::String(text.to_string()));
+    }
+    Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parsed(text: &str) -> (Format, Value) {
+        let (format, value) = parse_document(text).unwrap();
+        (format, value.unwrap())
+    }
+
+    #[test]
+    fn test_parse_documents() {
+        assert_eq!(
+            parsed(r#"{"a": [1, 2]}"#),
+            (Format::Json, json!({"a": [1, 2]}))
+        );
+        assert_eq!(
+            parsed("apiVersion: v1\nkind: Pod\nspec:\n  containers:\n    - name: web\n"),
+            (
+                Format::Yaml,
+                json!({"apiVersion": "v1", "kind": "Pod", "spec": {"containers": [{"name": "web"}]}})
+            )
+        );
+        assert_eq!(
+            parsed("[package]\nname = \"demo\"\nedition = 2018\n"),
+            (
+                Format::Toml,
+                json!({"package": {"name": "demo", "edition": 2018}})
+            )
+        );
+        assert_eq!(
+            parsed(
+                r#"<?xml version="1.0"?><feed lang="en"><entry>a</entry><entry>b</entry></feed>"#
+            ),
+            (
+                Format::Xml,
+                json!({"feed": {"@lang": "en", "entry": ["a", "b"]}})
+            )
+        );
+    }
+
+    #[test]
+    fn test_nearly_valid_and_plain_text() {
+        let (format, result) = parse_document(r#"{"name": "demo", "tags": ["a", "b",]}"#).unwrap();
+        assert_eq!(format, Format::Json);
+        assert!(result.unwrap_err().contains("line 1"));
+        let (format, result) = parse_document("[server]\nport = 80\nport = 81").unwrap();
+        assert_eq!(format, Format::Toml);
+        assert_eq!(
+            result.unwrap_err(),
+            "duplicate key `port` in table `server` at line 3"
+        );
+
+        assert_eq!(parse_document("Note: bring snacks"), None);
+        assert_eq!(parse_document("[docs](https://example.com)"), None);
+        assert_eq!(parse_document("42"), None);
+    }
+}
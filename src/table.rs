@@ -0,0 +1,221 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+/// A table copied from a spreadsheet or a web page.
+///
+/// Cells are stored as JSON values: numbers and booleans are parsed, dates are
+/// written in the ISO 8601 format and empty cells are `null`. The headers are
+/// empty when the first row does not look like a header.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+lazy_static! {
+    static ref INTEGER: Regex = Regex::new(r"^[-+]?(?:0|[1-9]\d*|\d{1,3}(?:,\d{3})+)$").unwrap();
+    static ref DECIMAL: Regex = Regex::new(
+        r"^[-+]?(?:\d+|\d{1,3}(?:,\d{3})+)?\.\d+(?:[eE][-+]?\d+)?$|^[-+]?\d+[eE][-+]?\d+$"
+    )
+    .unwrap();
+}
+
+impl Table {
+    /// Builds a table out of rows of cell text. `header` tells whether the
+    /// first row is a header, which is guessed when it is `None`.
+    pub fn from_rows(mut rows: Vec<Vec<String>>, header: Option<bool>) -> Option<Self> {
+        rows.retain(|row| row.iter().any(|cell| !cell.trim().is_empty()));
+        let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+        let filled = (0..columns)
+            .filter(|&i| {
+                rows.iter()
+                    .any(|row| row.get(i).is_some_and(|cell| !cell.trim().is_empty()))
+            })
+            .count();
+        if rows.len() < 2 || filled < 2 {
+            return None;
+        }
+
+        let header = header.unwrap_or_else(|| {
+            rows[0].len() == columns
+                && rows[0]
+                    .iter()
+                    .all(|cell| matches!(parse_cell(cell), Value::String(_)))
+                && rows[1..]
+                    .iter()
+                    .flatten()
+                    .any(|cell| !matches!(parse_cell(cell), Value::String(_)))
+        });
+        let headers = if header {
+            rows.remove(0)
+                .into_iter()
+                .map(|cell| cell.trim().to_string())
+                .collect()
+        } else {
+            vec![]
+        };
+        let rows = rows
+            .iter()
+            .map(|row| {
+                (0..columns)
+                    .map(|i| row.get(i).map_or(Value::Null, |cell| parse_cell(cell)))
+                    .collect()
+            })
+            .collect();
+        Some(Table { headers, rows })
+    }
+
+    /// Detects tab or comma separated values in text, as spreadsheets put on
+    /// the clipboard. Every row needs the same number of cells.
+    pub fn from_text(text: &str) -> Option<Self> {
+        let text = text.trim_end_matches(['\r', '\n']);
+        for delimiter in ['\t', ',', ';'] {
+            let rows = match split_rows(text, delimiter) {
+                Some(rows) => rows,
+                None => continue,
+            };
+            let columns = rows[0].len();
+            if rows.len() < 2 || columns < 2 || rows.iter().any(|row| row.len() != columns) {
+                continue;
+            }
+            // Lines that are all indented with tabs are code rather than a table
+            if rows.iter().all(|row| row[0].is_empty()) {
+                continue;
+            }
+            // Commas in prose are followed by a space, unlike in exported CSV
+            if delimiter != '\t' {
+                let cells = rows.iter().flat_map(|row| &row[1..]).collect::<Vec<_>>();
+                let spaced = cells.iter().filter(|cell| cell.starts_with(' ')).count();
+                if spaced * 2 >= cells.len() {
+                    continue;
+                }
+            }
+            return Table::from_rows(rows, None);
+        }
+        None
+    }
+}
+
+/// Splits the text into rows of cells, handling quoted cells that contain the
+/// delimiter, line breaks or escaped `""` quotes. Returns `None` when the
+/// delimiter does not appear at all or a quote is left open.
+fn split_rows(text: &str, delimiter: char) -> Option<Vec<Vec<String>>> {
+    if !text.contains(delimiter) {
+        return None;
+    }
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if cell.is_empty() => quoted = true,
+            c if quoted => cell.push(c),
+            c if c == delimiter => row.push(std::mem::take(&mut cell)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => cell.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    row.push(cell);
+    rows.push(row);
+    Some(rows)
+}
+
+/// Converts the text of a cell into a typed value.
+pub fn parse_cell(cell: &str) -> Value {
+    let cell = cell.trim();
+    if cell.is_empty() {
+        return Value::Null;
+    }
+    if cell.eq_ignore_ascii_case("true") || cell.eq_ignore_ascii_case("false") {
+        return Value::Bool(cell.eq_ignore_ascii_case("true"));
+    }
+
+    // Numbers with leading zeros like zip codes are kept as text
+    let number = cell.replace(',', "");
+    if INTEGER.is_match(cell) {
+        if let Ok(integer) = number.parse::<i64>() {
+            return Value::from(integer);
+        }
+    }
+    if DECIMAL.is_match(cell) {
+        if let Some(decimal) = number.parse().ok().and_then(Number::from_f64) {
+            return Value::Number(decimal);
+        }
+    }
+
+    const DATES: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%d.%m.%Y", "%m/%d/%Y", "%d/%m/%Y"];
+    const DATE_TIMES: &[&str] = &["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"];
+    if let Some(date) = DATES
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(cell, format).ok())
+    {
+        return Value::String(date.format("%Y-%m-%d").to_string());
+    }
+    if let Some(time) = DATE_TIMES
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(cell, format).ok())
+    {
+        return Value::String(time.format("%Y-%m-%dT%H:%M:%S").to_string());
+    }
+    Value::String(cell.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_tab_separated_copy() {
+        let text = "Item\tQty\tPrice\tPaid\tDue\r\nApples\t3\t1,250.50\tTRUE\t03/14/2024\r\n\"Pears\nGreen\"\t\t0.5\tfalse\t2024-04-01\r\n";
+        let table = Table::from_text(text).unwrap();
+        assert_eq!(table.headers, vec!["Item", "Qty", "Price", "Paid", "Due"]);
+        assert_eq!(
+            table.rows,
+            vec![
+                vec![
+                    json!("Apples"),
+                    json!(3),
+                    json!(1250.5),
+                    json!(true),
+                    json!("2024-03-14")
+                ],
+                vec![
+                    json!("Pears\nGreen"),
+                    json!(null),
+                    json!(0.5),
+                    json!(false),
+                    json!("2024-04-01")
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_that_is_not_a_table() {
+        assert_eq!(Table::from_text("Hello, world\nBye, now"), None);
+        assert_eq!(Table::from_text("one line\tonly"), None);
+        assert_eq!(Table::from_text("a,b\nc"), None);
+        assert_eq!(Table::from_text("\tfoo();\n\tbar();"), None);
+        assert_eq!(parse_cell("02134"), json!("02134"));
+        let table = Table::from_text("id,name\n1,a\n2,b").unwrap();
+        assert_eq!(table.rows[1], vec![json!(2), json!("b")]);
+    }
+}
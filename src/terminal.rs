@@ -0,0 +1,216 @@
+use crate::common::ClipboardData;
+use crate::pipeline::Stage;
+use failure::Error;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+use unicode_normalization::UnicodeNormalization;
+
+lazy_static! {
+    /// CSI sequences such as colours, OSC sequences such as window titles and
+    /// hyperlinks, and the remaining two byte escapes
+    static ref ANSI_ESCAPE: Regex = Regex::new(concat!(
+        r"\x1b\[[0-?]*[ -/]*[@-~]",
+        r"|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)",
+        r"|\x1b[@-Z\\-_]"
+    ))
+    .unwrap();
+    /// Prompts of bash, zsh, fish, PowerShell, cmd and the Python REPL,
+    /// optionally preceded by the name of a virtual environment
+    static ref PROMPT: Regex = Regex::new(concat!(
+        r"^(?:\([\w.-]+\) )?(?:",
+        r"[\w.-]+@[\w.-]+:[^\s$#]*[$#]",
+        r"|\[[\w.-]+@[\w.-]+ [^\]]*\][$#]",
+        r"|PS [A-Za-z]:\\[^>]*>",
+        r"|[A-Za-z]:\\[^>]*>",
+        r"|[$%❯]|>>>",
+        r")(?: |$)"
+    ))
+    .unwrap();
+}
+
+/// Stage that cleans up text copied from a terminal. Colour and other escape
+/// sequences and trailing whitespace are removed, then shell prompts are
+/// stripped, lines that the terminal wrapped are joined back and the text is
+/// normalised to NFC. The text as it was copied is kept in `raw`.
+///
+/// Only text copied from one of the `owner_classes` is cleaned up, or text
+/// that contains escape sequences or starts with a prompt when `detect` is set.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerminalCleanup {
+    /// Window classes of terminal emulators, compared case-insensitively
+    pub owner_classes: Vec<String>,
+    /// Also cleans up text from other windows when it looks like a terminal copy
+    pub detect: bool,
+    pub strip_prompts: bool,
+    pub unwrap_lines: bool,
+    /// Width at which the terminal wraps lines. When it is not set, lines are
+    /// only joined when at least two of them have the maximum length.
+    pub wrap_width: Option<usize>,
+    pub normalize: bool,
+}
+
+impl Default for TerminalCleanup {
+    fn default() -> Self {
+        TerminalCleanup {
+            owner_classes: [
+                "alacritty",
+                "cascadia_hosting_window_class",
+                "console_window_class",
+                "foot",
+                "gnome-terminal-server",
+                "kitty",
+                "konsole",
+                "mintty",
+                "org.wezfurlong.wezterm",
+                "st-256color",
+                "terminator",
+                "tilix",
+                "urxvt",
+                "xfce4-terminal",
+                "xterm",
+            ]
+            .iter()
+            .map(|class| class.to_string())
+            .collect(),
+            detect: true,
+            strip_prompts: true,
+            unwrap_lines: true,
+            wrap_width: None,
+            normalize: true,
+        }
+    }
+}
+
+impl TerminalCleanup {
+    /// Returns the cleaned up text.
+    pub fn clean(&self, text: &str) -> String {
+        let text = ANSI_ESCAPE.replace_all(text, "");
+        let mut lines = text.lines().map(str::trim_end).collect::<Vec<_>>();
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+
+        let mut lines = if self.unwrap_lines {
+            unwrap(&lines, self.wrap_width)
+        } else {
+            lines.iter().map(|line| line.to_string()).collect()
+        };
+        if self.strip_prompts {
+            for line in &mut lines {
+                if let Some(prompt) = PROMPT.find(line) {
+                    line.replace_range(..prompt.end(), "");
+                }
+            }
+        }
+
+        let text = lines.join("\n");
+        if self.normalize {
+            text.nfc().collect()
+        } else {
+            text
+        }
+    }
+
+    /// Checks whether the text was copied from a terminal.
+    fn applies_to_text(&self, content: &str, owner_class: Option<&str>) -> bool {
+        let from_terminal = owner_class.is_some_and(|class| {
+            self.owner_classes
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(class))
+        });
+        from_terminal || self.detect && looks_like_terminal(content)
+    }
+}
+
+impl Stage for TerminalCleanup {
+    fn process(&mut self, mut data: ClipboardData) -> Result<Option<ClipboardData>, Error> {
+        if let ClipboardData::UnicodeText { content, meta, .. } = &mut data {
+            if !self.applies_to_text(content, meta.owner_class.as_deref()) {
+                return Ok(Some(data));
+            }
+            let cleaned = self.clean(content);
+            if cleaned != *content {
+                meta.raw = Some(std::mem::replace(content, cleaned));
+                data.refresh();
+            }
+        }
+        Ok(Some(data))
+    }
+}
+
+fn looks_like_terminal(text: &str) -> bool {
+    text.contains('\x1b')
+        || text
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .is_some_and(|line| {
+                // A line starting with a percentage is more likely than a
+                // csh prompt
+                PROMPT.is_match(line) && !line.starts_with('%') && line.len() > 2
+            })
+}
+
+/// Joins the lines that fill the whole width of the terminal with the line
+/// after them.
+fn unwrap(lines: &[&str], width: Option<usize>) -> Vec<String> {
+    let lengths = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .collect::<Vec<_>>();
+    let width = width.or_else(|| {
+        let longest = lengths.iter().copied().max()?;
+        let full = lengths.iter().filter(|&&length| length == longest).count();
+        if longest >= 40 && full >= 2 {
+            Some(longest)
+        } else {
+            None
+        }
+    });
+
+    let mut unwrapped: Vec<String> = vec![];
+    let mut continued = false;
+    for (line, length) in lines.iter().zip(lengths) {
+        match unwrapped.last_mut() {
+            Some(last) if continued => last.push_str(line),
+            _ => unwrapped.push(line.to_string()),
+        }
+        continued = width == Some(length);
+    }
+    unwrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean() {
+        let cleanup = TerminalCleanup::default();
+        let copy = "\x1b]0;~/demo\x07\x1b[01;32mme@box\x1b[00m:\x1b[01;34m~/demo\x1b[00m$ ls   \n\x1b[0m\x1b[01;34msrc\x1b[0m  Cargo.toml\n(venv) me@box:~/demo$ python -c 'print(\"cafe\u{301}\")'\ncafe\u{301}\n";
+        assert_eq!(
+            cleanup.clean(copy),
+            "ls\nsrc  Cargo.toml\npython -c 'print(\"caf\u{e9}\")'\ncaf\u{e9}"
+        );
+        assert_eq!(
+            cleanup.clean(">>> 1 + 1\n2\nPS C:\\Users\\me> dir"),
+            "1 + 1\n2\ndir"
+        );
+    }
+
+    #[test]
+    fn test_unwrap_and_detection() {
+        let cleanup = TerminalCleanup::default();
+        let wrapped = format!("$ echo {}\n{}\nc\ndone", "a".repeat(43), "b".repeat(50));
+        assert_eq!(
+            cleanup.clean(&wrapped),
+            format!("echo {}{}c\ndone", "a".repeat(43), "b".repeat(50))
+        );
+
+        assert!(cleanup.applies_to_text("$ cargo build", None));
+        assert!(cleanup.applies_to_text("cargo build", Some("Alacritty")));
+        assert!(!cleanup.applies_to_text("It costs $ 5", None));
+        assert!(!cleanup.applies_to_text("% of users", Some("firefox")));
+    }
+}
@@ -0,0 +1,227 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// A stack trace recognised in the copied content.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StackTrace {
+    /// `rust`, `python` or `java`
+    pub language: String,
+    /// Type of the exception, or `panic` for Rust
+    pub exception: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Frames with the innermost call first for Rust and Java and last for
+    /// Python, in the order they were printed
+    pub frames: Vec<Frame>,
+    /// The exception that caused this one, from a Java `Caused by:` section
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause: Option<Box<StackTrace>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Frame {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<u32>,
+}
+
+lazy_static! {
+    static ref RUST_PANIC: Regex = Regex::new(concat!(
+        r"(?m)^thread '(?P<thread>[^']*)' panicked at ",
+        r"(?:'(?P<old_message>.*)', )?(?P<file>[^\s:]+):(?P<line>\d+):(?P<column>\d+):?$"
+    ))
+    .unwrap();
+    static ref RUST_FRAME: Regex = Regex::new(r"^\s*\d+: (.+)$").unwrap();
+    static ref RUST_LOCATION: Regex = Regex::new(r"^\s*at (.+):(\d+):(\d+)$").unwrap();
+    static ref PYTHON_FRAME: Regex =
+        Regex::new(r#"^\s*File "(.+)", line (\d+)(?:, in (.+))?$"#).unwrap();
+    static ref PYTHON_EXCEPTION: Regex = Regex::new(r"^([A-Za-z_][\w.]*)(?::\s*(.*))?$").unwrap();
+    static ref JAVA_EXCEPTION: Regex = Regex::new(concat!(
+        r#"^(?:Exception in thread "[^"]*" |Caused by: )?"#,
+        r"([A-Za-z_$][\w$]*(?:\.[A-Za-z_$][\w$]*)*(?:Exception|Error|Throwable))(?::\s*(.*))?$"
+    ))
+    .unwrap();
+    static ref JAVA_FRAME: Regex =
+        Regex::new(r"^\s*at ([\w$.<>/]+)\((?:(.+?):(\d+)|[^)]*)\)$").unwrap();
+}
+
+/// Finds a Rust panic, a Python traceback or a Java exception in the text.
+pub fn parse_stack_trace(text: &str) -> Option<StackTrace> {
+    parse_rust(text)
+        .or_else(|| parse_python(text))
+        .or_else(|| parse_java(text))
+}
+
+fn parse_rust(text: &str) -> Option<StackTrace> {
+    let panic = RUST_PANIC.captures(text)?;
+    let rest = &text[panic.get(0).unwrap().end()..];
+    // Since Rust 1.73 the message follows the location on its own lines
+    let message = match panic.name("old_message") {
+        Some(message) => message.as_str().to_string(),
+        None => rest
+            .lines()
+            .skip(1)
+            .take_while(|line| !line.starts_with("note:") && !line.starts_with("stack backtrace:"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+
+    let mut frames: Vec<Frame> = vec![];
+    for line in rest.lines() {
+        if let Some(location) = RUST_LOCATION.captures(line) {
+            if let Some(frame) = frames.last_mut() {
+                frame.file = Some(location[1].to_string());
+                frame.line = location[2].parse().ok();
+                frame.column = location[3].parse().ok();
+            }
+        } else if let Some(function) = RUST_FRAME.captures(line) {
+            frames.push(Frame {
+                function: Some(function[1].to_string()),
+                ..Frame::default()
+            });
+        }
+    }
+    if frames.is_empty() {
+        frames.push(Frame {
+            function: None,
+            file: Some(panic["file"].to_string()),
+            line: panic["line"].parse().ok(),
+            column: panic["column"].parse().ok(),
+        });
+    }
+
+    Some(StackTrace {
+        language: "rust".to_string(),
+        exception: "panic".to_string(),
+        message: Some(format!("thread '{}': {}", &panic["thread"], message.trim())),
+        frames,
+        cause: None,
+    })
+}
+
+fn parse_python(text: &str) -> Option<StackTrace> {
+    // With chained exceptions the last traceback is the one that was raised
+    let start = text.rfind("Traceback (most recent call last):")?;
+    let mut frames = vec![];
+    let mut exception = None;
+    for line in text[start..].lines().skip(1) {
+        if let Some(frame) = PYTHON_FRAME.captures(line) {
+            frames.push(Frame {
+                function: frame.get(3).map(|function| function.as_str().to_string()),
+                file: Some(frame[1].to_string()),
+                line: frame[2].parse().ok(),
+                column: None,
+            });
+        } else if !line.starts_with(' ') && !line.is_empty() {
+            exception = PYTHON_EXCEPTION.captures(line);
+            break;
+        }
+    }
+
+    let exception = exception?;
+    Some(StackTrace {
+        language: "python".to_string(),
+        exception: exception[1].to_string(),
+        message: exception.get(2).map(|message| message.as_str().to_string()),
+        frames,
+        cause: None,
+    })
+}
+
+fn parse_java(text: &str) -> Option<StackTrace> {
+    let lines = text.lines().collect::<Vec<_>>();
+    let start = lines.iter().position(|line| {
+        JAVA_EXCEPTION.is_match(line.trim()) && !line.trim_start().starts_with("Caused by:")
+    })?;
+    let mut traces: Vec<StackTrace> = vec![];
+    for line in &lines[start..] {
+        let trimmed = line.trim();
+        if let Some(frame) = JAVA_FRAME.captures(line) {
+            if let Some(trace) = traces.last_mut() {
+                trace.frames.push(Frame {
+                    function: Some(frame[1].to_string()),
+                    file: frame.get(2).map(|file| file.as_str().to_string()),
+                    line: frame.get(3).and_then(|line| line.as_str().parse().ok()),
+                    column: None,
+                });
+            }
+        } else if let Some(exception) = JAVA_EXCEPTION.captures(trimmed) {
+            if traces.is_empty() || trimmed.starts_with("Caused by:") {
+                traces.push(StackTrace {
+                    language: "java".to_string(),
+                    exception: exception[1].to_string(),
+                    message: exception.get(2).map(|message| message.as_str().to_string()),
+                    frames: vec![],
+                    cause: None,
+                });
+            }
+        } else if !trimmed.starts_with("...") {
+            break;
+        }
+    }
+
+    // A Java exception without frames is just a sentence mentioning one
+    if traces[0].frames.is_empty() {
+        return None;
+    }
+    let mut trace = traces.pop()?;
+    while let Some(mut parent) = traces.pop() {
+        parent.cause = Some(Box::new(trace));
+        trace = parent;
+    }
+    Some(trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rust_and_python() {
+        let panic = "thread 'main' panicked at src/main.rs:4:5:\ncalled `Option::unwrap()` on a `None` value\nstack backtrace:\n   0: rust_begin_unwind\n             at /rustc/abc/library/std/src/panicking.rs:645:5\n   1: demo::main\n             at ./src/main.rs:4:5\nnote: Some details are omitted";
+        let trace = parse_stack_trace(panic).unwrap();
+        assert_eq!(
+            trace.message.as_deref(),
+            Some("thread 'main': called `Option::unwrap()` on a `None` value")
+        );
+        assert_eq!(
+            trace.frames[1],
+            Frame {
+                function: Some("demo::main".to_string()),
+                file: Some("./src/main.rs".to_string()),
+                line: Some(4),
+                column: Some(5),
+            }
+        );
+
+        let traceback = "Traceback (most recent call last):\n  File \"app.py\", line 10, in <module>\n    main()\n  File \"app.py\", line 6, in main\n    raise ValueError(\"bad input\")\nValueError: bad input";
+        let trace = parse_stack_trace(traceback).unwrap();
+        assert_eq!(trace.exception, "ValueError");
+        assert_eq!(trace.message.as_deref(), Some("bad input"));
+        assert_eq!(trace.frames.len(), 2);
+        assert_eq!(trace.frames[1].function.as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn test_java() {
+        let exception = "Exception in thread \"main\" java.lang.IllegalStateException: boom\n\tat com.example.App.run(App.java:42)\n\tat com.example.App.main(App.java:10)\nCaused by: java.io.IOException: disk full\n\tat java.base/java.io.FileOutputStream.writeBytes(Native Method)\n\t... 2 more";
+        let trace = parse_stack_trace(exception).unwrap();
+        assert_eq!(trace.exception, "java.lang.IllegalStateException");
+        assert_eq!(trace.frames[0].file.as_deref(), Some("App.java"));
+        assert_eq!(trace.frames[0].line, Some(42));
+        let cause = trace.cause.unwrap();
+        assert_eq!(cause.message.as_deref(), Some("disk full"));
+        assert_eq!(cause.frames[0].file, None);
+
+        assert_eq!(
+            parse_stack_trace("We saw a NullPointerException today"),
+            None
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use crate::common::{ClipboardData, ClipboardWriter};
+use crate::pipeline::Stage;
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use url::{form_urlencoded, Host, Url};
+
+/// Query parameters added by ad networks, newsletters and social networks to
+/// track where a link was shared or clicked.
+const TRACKING_PARAMETERS: &[&str] = &[
+    "_ga",
+    "_gl",
+    "_hsenc",
+    "_hsmi",
+    "dclid",
+    "fbclid",
+    "gbraid",
+    "gclid",
+    "gclsrc",
+    "igshid",
+    "li_fat_id",
+    "mc_cid",
+    "mc_eid",
+    "mkt_tok",
+    "msclkid",
+    "oly_anon_id",
+    "oly_enc_id",
+    "ref_src",
+    "rb_clickid",
+    "s_cid",
+    "si",
+    "srsltid",
+    "ttclid",
+    "twclid",
+    "vero_id",
+    "wbraid",
+    "wickedid",
+    "yclid",
+];
+
+/// Prefixes of tracking parameters such as `utm_source` or `pk_campaign`.
+const TRACKING_PREFIXES: &[&str] = &["utm_", "pk_", "mtm_", "hsa_", "__hs"];
+
+/// Public suffixes made of more than one label under which domains are
+/// registered. Only the common ones are listed so that no copy of the Public
+/// Suffix List has to be downloaded; other hosts are registered directly under
+/// their last label.
+const PUBLIC_SUFFIXES: &[&str] = &[
+    "ac.jp",
+    "ac.uk",
+    "appspot.com",
+    "azurewebsites.net",
+    "blogspot.com",
+    "cloudfront.net",
+    "co.id",
+    "co.il",
+    "co.in",
+    "co.jp",
+    "co.kr",
+    "co.nz",
+    "co.th",
+    "co.uk",
+    "co.za",
+    "com.ar",
+    "com.au",
+    "com.br",
+    "com.cn",
+    "com.co",
+    "com.eg",
+    "com.hk",
+    "com.mx",
+    "com.my",
+    "com.pe",
+    "com.ph",
+    "com.pk",
+    "com.sa",
+    "com.sg",
+    "com.tr",
+    "com.tw",
+    "com.ua",
+    "com.vn",
+    "edu.au",
+    "firebaseapp.com",
+    "github.io",
+    "gitlab.io",
+    "go.jp",
+    "gov.au",
+    "gov.cn",
+    "gov.uk",
+    "herokuapp.com",
+    "ltd.uk",
+    "me.uk",
+    "ne.jp",
+    "net.au",
+    "net.br",
+    "net.cn",
+    "net.in",
+    "net.nz",
+    "netlify.app",
+    "or.jp",
+    "or.kr",
+    "org.au",
+    "org.br",
+    "org.cn",
+    "org.in",
+    "org.nz",
+    "org.uk",
+    "org.za",
+    "pages.dev",
+    "plc.uk",
+    "s3.amazonaws.com",
+    "vercel.app",
+    "web.app",
+    "workers.dev",
+];
+
+/// Components of a copied URL.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UrlParts {
+    pub scheme: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Domain that was registered under the public suffix, e.g. `example.co.uk`
+    /// for `www.example.co.uk`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registrable_domain: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    pub path: String,
+    /// Decoded query parameters. Parameters that are repeated have an array of
+    /// their values.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub query: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragment: Option<String>,
+}
+
+impl UrlParts {
+    /// Splits the URL into its components. URLs starting with `www.` are
+    /// assumed to use HTTPS.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (url, _) = parse(text)?;
+        let mut query = Map::new();
+        for (key, value) in url.query_pairs() {
+            let value = Value::String(value.into_owned());
+            match query.get_mut(key.as_ref()) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => {
+                    let first = existing.take();
+                    *existing = Value::Array(vec![first, value]);
+                }
+                None => {
+                    query.insert(key.into_owned(), value);
+                }
+            }
+        }
+        Some(UrlParts {
+            scheme: url.scheme().to_string(),
+            host: url.host_str().map(String::from),
+            registrable_domain: match url.host() {
+                Some(Host::Domain(domain)) => registrable_domain(domain),
+                _ => None,
+            },
+            port: url.port(),
+            path: url.path().to_string(),
+            query,
+            fragment: url.fragment().map(String::from),
+        })
+    }
+}
+
+/// Parses the URL, adding the scheme to URLs starting with `www.`. The flag
+/// tells whether the scheme was added.
+fn parse(text: &str) -> Option<(Url, bool)> {
+    let text = text.trim();
+    if text.starts_with("www.") {
+        return Url::parse(&format!("https://{}", text))
+            .ok()
+            .map(|url| (url, true));
+    }
+    Url::parse(text).ok().map(|url| (url, false))
+}
+
+fn registrable_domain(host: &str) -> Option<String> {
+    let host = host.trim_end_matches('.').to_lowercase();
+    let labels = host.split('.').collect::<Vec<_>>();
+    let suffix = PUBLIC_SUFFIXES
+        .iter()
+        .filter(|suffix| host.ends_with(&format!(".{}", suffix)))
+        .map(|suffix| suffix.split('.').count())
+        .max()
+        .unwrap_or(1);
+    if labels.len() <= suffix {
+        return None;
+    }
+    Some(labels[labels.len() - suffix - 1..].join("."))
+}
+
+/// Checks whether the query parameter is only used for tracking.
+pub fn is_tracking_parameter(name: &str) -> bool {
+    let name = name.to_lowercase();
+    TRACKING_PARAMETERS.contains(&name.as_str())
+        || TRACKING_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+/// Removes the tracking parameters from the URL. The rest of the query is
+/// kept as it was written, and the URL is returned unchanged when it has no
+/// tracking parameters.
+pub fn clean_url(text: &str) -> Option<String> {
+    let (mut url, prefixed) = parse(text)?;
+    let query = match url.query() {
+        Some(query) => query.to_string(),
+        None => return Some(text.trim().to_string()),
+    };
+    let kept = query
+        .split('&')
+        .filter(|pair| {
+            !form_urlencoded::parse(pair.as_bytes())
+                .next()
+                .is_some_and(|(name, _)| is_tracking_parameter(&name))
+        })
+        .collect::<Vec<_>>();
+    if kept.len() == query.split('&').count() {
+        return Some(text.trim().to_string());
+    }
+
+    let kept = kept.join("&");
+    url.set_query(
+        Some(&kept)
+            .filter(|query| !query.is_empty())
+            .map(String::as_str),
+    );
+    let clean = url.to_string();
+    Some(match clean.strip_prefix("https://") {
+        Some(clean) if prefixed => clean.to_string(),
+        _ => clean,
+    })
+}
+
+/// Stage that replaces copied URLs with their `clean_url`, keeping the URL as
+/// it was copied in `raw`.
+///
+/// With `write_back` the clean URL is also put back on the clipboard so that
+/// it is what gets pasted. The copy caused by writing to the clipboard is not
+/// recorded again.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct UrlCleaner {
+    pub write_back: bool,
+    /// Writes to the clipboard of the backend that is being watched
+    #[serde(skip)]
+    pub writer: Option<ClipboardWriter>,
+    /// Last URL written to the clipboard
+    #[serde(skip)]
+    written: Option<String>,
+}
+
+impl Stage for UrlCleaner {
+    fn process(&mut self, mut data: ClipboardData) -> Result<Option<ClipboardData>, Error> {
+        if self.written.take().is_some_and(|url| url == data.content()) {
+            return Ok(None);
+        }
+        let clean = match &data.meta().clean_url {
+            Some(clean) if clean != data.content() => clean.clone(),
+            _ => return Ok(Some(data)),
+        };
+
+        let raw = std::mem::replace(data.content_mut(), clean.clone());
+        data.meta_mut().raw = Some(raw);
+        data.refresh();
+        if let (true, Some(writer)) = (self.write_back, &self.writer) {
+            // The data is still recorded when the clipboard cannot be written
+            match writer.0(&clean) {
+                Ok(()) => self.written = Some(clean),
+                Err(e) => eprintln!("Could not write the clean URL to the clipboard: {}", e),
+            }
+        }
+        Ok(Some(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_url_parts() {
+        let parts =
+            UrlParts::parse("https://www.shop.example.co.uk:8443/a/b?q=rust&tag=1&tag=2#top")
+                .unwrap();
+        assert_eq!(parts.host.as_deref(), Some("www.shop.example.co.uk"));
+        assert_eq!(parts.registrable_domain.as_deref(), Some("example.co.uk"));
+        assert_eq!(parts.port, Some(8443));
+        assert_eq!(parts.path, "/a/b");
+        assert_eq!(
+            Value::Object(parts.query),
+            json!({"q": "rust", "tag": ["1", "2"]})
+        );
+        assert_eq!(parts.fragment.as_deref(), Some("top"));
+
+        let parts = UrlParts::parse("www.github.com").unwrap();
+        assert_eq!(parts.scheme, "https");
+        assert_eq!(parts.registrable_domain.as_deref(), Some("github.com"));
+        assert_eq!(
+            UrlParts::parse("http://127.0.0.1/")
+                .unwrap()
+                .registrable_domain,
+            None
+        );
+    }
+
+    #[test]
+    fn test_clean_url() {
+        assert_eq!(
+            clean_url("https://example.com/post?id=7&utm_source=news&q=a%20b&fbclid=x#c").unwrap(),
+            "https://example.com/post?id=7&q=a%20b#c"
+        );
+        assert_eq!(
+            clean_url("www.example.com/?gclid=1").unwrap(),
+            "www.example.com/"
+        );
+        assert_eq!(
+            clean_url("https://example.com").unwrap(),
+            "https://example.com"
+        );
+
+        let written = Arc::new(Mutex::new(vec![]));
+        let log = written.clone();
+        let mut cleaner = UrlCleaner {
+            write_back: true,
+            writer: Some(ClipboardWriter::new(move |text| {
+                log.lock().unwrap().push(text.to_string());
+                Ok(())
+            })),
+            ..UrlCleaner::default()
+        };
+        let data = ClipboardData::new(("https://example.com/?utm_medium=x".to_string(), None));
+        let data = cleaner.process(data).unwrap().unwrap();
+        assert_eq!(data.content(), "https://example.com/");
+        assert_eq!(
+            data.meta().raw.as_deref(),
+            Some("https://example.com/?utm_medium=x")
+        );
+        assert_eq!(*written.lock().unwrap(), vec!["https://example.com/"]);
+        let echo = ClipboardData::new(("https://example.com/".to_string(), None));
+        assert!(cleaner.process(echo).unwrap().is_none());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::common::{
+    ClipboardData, ClipboardFunctions, ClipboardSink, ClipboardTargets, ClipboardWriter, Selection,
+    WatchOptions,
+};
+use crate::debounce::Debouncer;
+use failure::{bail, format_err, Error};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Instant;
+
+/// Talks to the Wayland clipboard through the `wl-paste` utility from
+/// [wl-clipboard](https://github.com/bugaevc/wl-clipboard).
+///
+/// Wayland does not let regular clients read the clipboard in the background,
+/// so the compositor needs to support the `wlr-data-control` protocol that
+/// `wl-paste --watch` relies on. Wayland also does not expose the owner of the
+/// selection, so the owner is always empty.
+pub struct ClipboardOwner {
+    /// Name or path of the `wl-paste` executable
+    program: String,
+}
+
+impl ClipboardOwner {
+    /// Creates a new instance of the clipboard after making sure that
+    /// `wl-paste` can be run.
+    pub fn new() -> Result<Self, Error> {
+        ClipboardOwner::probe()?;
+        Ok(ClipboardOwner {
+            program: "wl-paste".to_string(),
+        })
+    }
+
+    /// Checks that the `wl-paste` executable is installed.
+    pub fn probe() -> Result<(), Error> {
+        let status = Command::new("wl-paste")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|e| format_err!("Could not run wl-paste: {}", e))?;
+        if !status.success() {
+            bail!("wl-paste exited with {}", status);
+        }
+        Ok(())
+    }
+
+    /// Runs `wl-paste` with the given arguments and returns its output.
+    fn paste(&self, args: &[&str]) -> Result<String, Error> {
+        let output = Command::new(&self.program).args(args).output()?;
+        if !output.status.success() {
+            bail!(
+                "wl-paste failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
+    /// Gets the MIME types offered by the owner of the selection.
+    fn get_selection_targets(&self, selection: Selection) -> Result<Vec<String>, Error> {
+        let types = self.paste(&with_selection(selection, &["--list-types"]))?;
+        Ok(types.lines().map(|line| line.to_string()).collect())
+    }
+
+    /// Fetches the selection as HTML if the owner offers it, or as plain text.
+    fn get_selection(&self, selection: Selection) -> Result<ClipboardData, Error> {
+        let targets = self.get_selection_targets(selection)?;
+
+        let mut data = if targets.iter().any(|target| target == "text/html") {
+            let args = with_selection(selection, &["--no-newline", "--type", "text/html"]);
+            ClipboardData::new((self.paste(&args)?, None, None))
+        } else {
+            let target = [
+                "text/plain;charset=utf-8",
+                "UTF8_STRING",
+                "text/plain",
+                "TEXT",
+            ]
+            .iter()
+            .find(|name| targets.iter().any(|target| target == *name))
+            .ok_or_else(|| format_err!("No text-based targets found."))?;
+            let args = with_selection(selection, &["--no-newline", "--type", target]);
+            ClipboardData::new((self.paste(&args)?, None))
+        };
+        data.meta_mut().selection = Some(selection);
+        Ok(data)
+    }
+
+    /// Spawns `wl-paste --watch` for the selection and sends the selection to
+    /// the channel every time it changes.
+    fn spawn_watcher(
+        &self,
+        selection: Selection,
+        changes: Sender<Selection>,
+    ) -> Result<Child, Error> {
+        let mut child = Command::new(&self.program)
+            .args(with_selection(selection, &["--watch", "echo"]))
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format_err!("Could not run {} --watch: {}", self.program, e))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| format_err!("{} has no stdout", self.program))?;
+
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                if let Err(e) = line {
+                    eprintln!("Could not read from wl-paste {}", e);
+                    break;
+                }
+                if changes.send(selection).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(child)
+    }
+}
+
+/// Adds the flag that makes `wl-paste` use the primary selection.
+fn with_selection<'a>(selection: Selection, args: &[&'a str]) -> Vec<&'a str> {
+    match selection {
+        Selection::Clipboard => args.to_vec(),
+        Selection::Primary => std::iter::once("--primary")
+            .chain(args.iter().cloned())
+            .collect(),
+    }
+}
+
+impl ClipboardFunctions for ClipboardOwner {
+    /// Gets the list of MIME types offered by the selection owner.
+    fn get_targets(&self) -> Result<ClipboardTargets, Error> {
+        Ok(ClipboardTargets::MIME(
+            self.get_selection_targets(Selection::Clipboard)?,
+        ))
+    }
+
+    /// Fetches the clipboard as HTML if the owner offers it, or as plain text.
+    fn get_clipboard(&self) -> Result<ClipboardData, Error> {
+        self.get_selection(Selection::Clipboard)
+    }
+
+    /// Writes the text with `wl-copy`, which keeps serving the clipboard in
+    /// the background until something else is copied.
+    fn writer(&self) -> Option<ClipboardWriter> {
+        Some(ClipboardWriter::new(|text| {
+            let mut child = Command::new("wl-copy")
+                .stdin(Stdio::piped())
+                .spawn()
+                .map_err(|e| format_err!("Could not run wl-copy: {}", e))?;
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(text.as_bytes())?;
+            }
+            let status = child.wait()?;
+            if !status.success() {
+                bail!("wl-copy exited with {}", status);
+            }
+            Ok(())
+        }))
+    }
+
+    /// Watches the clipboard for changes with the default watch options.
+    fn watch_clipboard(&self, callback: &ClipboardSink) {
+        self.watch_selections(callback, &WatchOptions::default())
+    }
+
+    /// Spawns `wl-paste --watch` for every selection, each of which prints a
+    /// line every time its selection changes, and fetches the selection once
+    /// it has been quiet for its debounce window.
+    fn watch_selections(&self, callback: &ClipboardSink, options: &WatchOptions) {
+        let (sender, changes) = mpsc::channel();
+        let mut children = options
+            .windows()
+            .into_iter()
+            .filter_map(
+                |(selection, _)| match self.spawn_watcher(selection, sender.clone()) {
+                    Ok(child) => Some(child),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        None
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+        drop(sender);
+        let mut debouncer = Debouncer::new(options.windows());
+
+        loop {
+            let change = match debouncer.timeout(Instant::now()) {
+                Some(timeout) => changes.recv_timeout(timeout),
+                None => changes.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            // Once every watcher has exited, read what is still pending so the
+            // last copy is not lost
+            let (due, done) = match change {
+                Ok(selection) => {
+                    debouncer.touch(selection, Instant::now());
+                    (debouncer.due(Instant::now()), false)
+                }
+                Err(RecvTimeoutError::Timeout) => (debouncer.due(Instant::now()), false),
+                Err(RecvTimeoutError::Disconnected) => (debouncer.flush(), true),
+            };
+
+            for selection in due {
+                match self.get_selection(selection) {
+                    Ok(data) => {
+                        if let Err(e) = callback.0(data) {
+                            eprintln!("An error has occured in the callback function {}", e);
+                        }
+                    }
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+            if done {
+                break;
+            }
+        }
+
+        for child in children.iter_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
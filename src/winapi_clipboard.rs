@@ -1,4 +1,7 @@
-use crate::common::{ClipboardData, ClipboardFunctions, ClipboardSink, ClipboardTargets};
+use crate::common::{
+    ClipboardData, ClipboardFunctions, ClipboardSink, ClipboardTargets, ClipboardWriter, Selection,
+    WatchOptions,
+};
 use failure::{bail, format_err, Error};
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -16,20 +19,60 @@ use winapi::shared::minwindef::{LPARAM, LRESULT, WPARAM};
 use winapi::shared::windef::{HWND, POINT};
 use winapi::shared::winerror::ERROR_SUCCESS;
 use winapi::um::libloaderapi::GetModuleHandleW;
-use winapi::um::winbase::{GlobalLock, GlobalSize, GlobalUnlock};
+use winapi::um::winbase::{
+    GlobalAlloc, GlobalFree, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE,
+};
 use winapi::um::winuser::{
     AddClipboardFormatListener, CloseClipboard, CreateWindowExW, DefWindowProcW, DestroyWindow,
-    DispatchMessageW, EnumClipboardFormats, GetClipboardData, GetClipboardFormatNameW,
-    GetForegroundWindow, GetMessageW, GetWindowTextW, IsClipboardFormatAvailable, OpenClipboard,
-    PostQuitMessage, RegisterClassW, RegisterClipboardFormatW, RemoveClipboardFormatListener,
+    DispatchMessageW, EmptyClipboard, EnumClipboardFormats, GetClassNameW, GetClipboardData,
+    GetClipboardFormatNameW, GetForegroundWindow, GetMessageW, GetWindowTextW,
+    IsClipboardFormatAvailable, KillTimer, OpenClipboard, PostQuitMessage, RegisterClassW,
+    RegisterClipboardFormatW, RemoveClipboardFormatListener, SetClipboardData, SetTimer,
     TranslateMessage, CF_BITMAP, CF_DIB, CF_DIBV5, CF_DIF, CF_DSPBITMAP, CF_DSPENHMETAFILE,
     CF_DSPMETAFILEPICT, CF_DSPTEXT, CF_ENHMETAFILE, CF_GDIOBJFIRST, CF_GDIOBJLAST, CF_HDROP,
     CF_LOCALE, CF_METAFILEPICT, CF_OEMTEXT, CF_OWNERDISPLAY, CF_PALETTE, CF_PENDATA,
     CF_PRIVATEFIRST, CF_PRIVATELAST, CF_RIFF, CF_SYLK, CF_TEXT, CF_TIFF, CF_UNICODETEXT, CF_WAVE,
-    CS_OWNDC, CW_USEDEFAULT, HWND_MESSAGE, MSG, WM_CLIPBOARDUPDATE, WM_DESTROY, WNDCLASSW,
-    WS_MINIMIZE,
+    CS_OWNDC, CW_USEDEFAULT, HWND_MESSAGE, MSG, WM_CLIPBOARDUPDATE, WM_DESTROY, WM_TIMER,
+    WNDCLASSW, WS_MINIMIZE,
 };
 
+/// Replaces the clipboard with the text in the UTF-16 format. The memory is
+/// owned by the system once `SetClipboardData` succeeds.
+fn set_clipboard(text: &str) -> Result<(), Error> {
+    let wide: Vec<u16> = OsStr::new(text).encode_wide().chain(once(0)).collect();
+    unsafe {
+        if OpenClipboard(null_mut()) == 0 {
+            bail!(io::Error::last_os_error());
+        }
+        defer! {{
+            CloseClipboard();
+        }}
+
+        if EmptyClipboard() == 0 {
+            bail!(io::Error::last_os_error());
+        }
+        let handle = GlobalAlloc(GMEM_MOVEABLE, wide.len() * mem::size_of::<u16>());
+        if handle.is_null() {
+            bail!(io::Error::last_os_error());
+        }
+        let data = GlobalLock(handle) as *mut u16;
+        if data.is_null() {
+            GlobalFree(handle);
+            bail!(io::Error::last_os_error());
+        }
+        std::ptr::copy_nonoverlapping(wide.as_ptr(), data, wide.len());
+        GlobalUnlock(handle);
+        if SetClipboardData(CF_UNICODETEXT, handle).is_null() {
+            GlobalFree(handle);
+            bail!(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Identifier of the timer that delays reading the clipboard until it settles.
+const DEBOUNCE_TIMER_ID: usize = 1;
+
 /// Gets a hashset of all the data formats available on the clipboard.
 ///
 /// If the format is a standard clipboard format then its name and description
@@ -90,15 +133,22 @@ fn get_clipboard() -> Result<ClipboardData, Error> {
             .chain(once(0))
             .collect();
         let cf_html = RegisterClipboardFormatW(html_wide.as_ptr());
-        let owner = GetForegroundWindow();
-        let owner = if owner.is_null() {
+        let owner_window = GetForegroundWindow();
+        let owner = if owner_window.is_null() {
             None
         } else {
             let mut raw_data: [u16; 255] = mem::uninitialized();
-            let data_len = GetWindowTextW(owner, raw_data.as_mut_ptr(), 255) as usize;
+            let data_len = GetWindowTextW(owner_window, raw_data.as_mut_ptr(), 255) as usize;
             let owner_title = String::from_utf16_lossy(&raw_data[0..data_len]);
             Some(owner_title)
         };
+        let owner_class = if owner_window.is_null() {
+            None
+        } else {
+            let mut raw_data = [0u16; 256];
+            let data_len = GetClassNameW(owner_window, raw_data.as_mut_ptr(), 256) as usize;
+            Some(String::from_utf16_lossy(&raw_data[0..data_len])).filter(|class| !class.is_empty())
+        };
         let clipboard_data = if formats.contains(&cf_html) {
             let data = GetClipboardData(cf_html);
             if data.is_null() {
@@ -146,16 +196,35 @@ fn get_clipboard() -> Result<ClipboardData, Error> {
         } else {
             bail!("Non-text format not available")
         };
-        clipboard_data
+        clipboard_data.map(|mut data: ClipboardData| {
+            data.meta_mut().owner_class = owner_class;
+            data
+        })
     }
 }
 
-/// The callback function called by Windows in response to incoming message queues.
-/// This function is used to listen for `WM_CLIPBOARDUPDATE` events and calls the
-/// callback function stored in a global variable by getting the new data from
-/// the clipboard. The function prints an error message when something wrong happens
+/// Gets the new data from the clipboard and calls the callback function stored
+/// in a global variable. It prints an error message when something wrong happens
 /// like a non-text  format is pasted to the clipboard and it cannot be converted
 /// to text-based format.
+fn notify_callback() {
+    let data = get_clipboard();
+    if data.is_ok() {
+        let callback = CLIPBOARD.lock().unwrap().clone().unwrap();
+        if let Err(e) = callback.0(data.unwrap()) {
+            eprintln!("An error has occured in the callback function {}", e);
+        }
+    } else {
+        let err_msg = data.unwrap_err();
+        eprintln!("An error occured: {}", err_msg);
+    }
+}
+
+/// The callback function called by Windows in response to incoming message queues.
+/// This function is used to listen for `WM_CLIPBOARDUPDATE` events and notifies
+/// the callback function about the new data. When a debounce window is set, each
+/// update (re)starts a timer instead and the clipboard is only read once the timer
+/// fires, that is when the clipboard has not changed for the whole window.
 #[allow(dead_code)]
 unsafe extern "system" fn wnd_proc(
     hwnd: HWND,
@@ -165,15 +234,19 @@ unsafe extern "system" fn wnd_proc(
 ) -> LRESULT {
     match msg {
         WM_CLIPBOARDUPDATE => {
-            let data = get_clipboard();
-            if data.is_ok() {
-                CLIPBOARD.lock().unwrap().as_ref().unwrap().0(data.unwrap()).unwrap();
+            let debounce_ms = *DEBOUNCE_MS.lock().unwrap();
+            if debounce_ms > 0 {
+                SetTimer(hwnd, DEBOUNCE_TIMER_ID, debounce_ms, None);
             } else {
-                let err_msg = data.unwrap_err();
-                eprintln!("An error occured: {}", err_msg);
+                notify_callback();
             }
             1
         }
+        WM_TIMER if wparam == DEBOUNCE_TIMER_ID => {
+            KillTimer(hwnd, DEBOUNCE_TIMER_ID);
+            notify_callback();
+            0
+        }
         WM_DESTROY => {
             PostQuitMessage(0);
             0
@@ -312,11 +385,31 @@ impl ClipboardFunctions for ClipboardOwner {
         get_clipboard()
     }
 
-    /// Adds the window to the clipboard format listener list, sets up the window
-    /// to listen for events and stores the callback function in a global variable.
+    fn writer(&self) -> Option<ClipboardWriter> {
+        Some(ClipboardWriter::new(set_clipboard))
+    }
+
+    /// Watches the clipboard for changes with the default watch options.
     fn watch_clipboard(&self, callback: &ClipboardSink) {
+        self.watch_selections(callback, &WatchOptions::default())
+    }
+
+    /// Adds the window to the clipboard format listener list, sets up the window
+    /// to listen for events and stores the callback function and the debounce
+    /// window in global variables. Windows only has the clipboard selection.
+    fn watch_selections(&self, callback: &ClipboardSink, options: &WatchOptions) {
         unsafe {
             *CLIPBOARD.lock().unwrap() = Some(callback.clone());
+            for (selection, window) in options.windows() {
+                match selection {
+                    Selection::Clipboard => {
+                        *DEBOUNCE_MS.lock().unwrap() = window.as_millis() as u32
+                    }
+                    Selection::Primary => {
+                        eprintln!("The primary selection is not available on Windows")
+                    }
+                }
+            }
             let mut msg = MSG {
                 hwnd: self.0,
                 message: 0,
@@ -373,6 +466,8 @@ lazy_static! {
     /// as a paramter since an unsafe function cannot capture the paramter variables
     /// (closures cannot be unsafe).
     static ref CLIPBOARD: Mutex<Option<ClipboardSink>> = Mutex::new(None);
+    /// Milliseconds the clipboard needs to stay unchanged before it is read.
+    static ref DEBOUNCE_MS: Mutex<u32> = Mutex::new(0);
     /// Used for extracting the fields in the HTML Clipboard. The StartFragment
     /// and EndFragment is used to exactly extract the HTML Clipboard selection.
     /// The source url is optional since applications such as Electron-based
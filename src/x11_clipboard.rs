@@ -1,14 +1,24 @@
-use crate::common::{ClipboardData, ClipboardFunctions, ClipboardSink, ClipboardTargets};
+use crate::common::{
+    ClipboardData, ClipboardFunctions, ClipboardSink, ClipboardTargets, ClipboardWriter, Selection,
+    WatchOptions,
+};
+use crate::debounce::Debouncer;
 use failure::{bail, format_err, Error};
 use std::collections::HashMap;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::mem;
 use std::os::raw::{c_char, c_int, c_long, c_uchar, c_ulong};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
 use x11::xlib::{
-    AnyPropertyType, Atom, CurrentTime, Display, False, SelectionNotify, Window, XCloseDisplay,
-    XConvertSelection, XCreateSimpleWindow, XDefaultRootWindow, XDeleteProperty, XDestroyWindow,
-    XEvent, XFetchName, XGetAtomName, XGetSelectionOwner, XGetWindowProperty, XInternAtom,
-    XNextEvent, XOpenDisplay, XSelectInput, XA_ATOM,
+    AnyPropertyType, Atom, Bool, CurrentTime, Display, False, PropModeReplace, SelectionClear,
+    SelectionNotify, SelectionRequest, Time, True, Window, XChangeProperty, XClassHint,
+    XCloseDisplay, XConnectionNumber, XConvertSelection, XCreateSimpleWindow, XDefaultRootWindow,
+    XDeleteProperty, XDestroyWindow, XEvent, XFetchName, XFlush, XFree, XGetAtomName,
+    XGetClassHint, XGetSelectionOwner, XGetWindowProperty, XIfEvent, XInternAtom, XMaxRequestSize,
+    XNextEvent, XOpenDisplay, XPending, XSelectInput, XSelectionEvent, XSelectionRequestEvent,
+    XSendEvent, XSetSelectionOwner, XA_ATOM, XA_STRING,
 };
 
 /// Represents a windowless X11 Client and its connection to the X11 Server.
@@ -31,6 +41,32 @@ extern "C" {
     fn XFixesQueryExtension(_3: *mut Display, _2: *mut c_int, _1: *mut c_int) -> c_int;
 }
 
+/// Event sent by the XFixes extension when the owner of a selection changes.
+/// It is not included in the X11 bindings either.
+#[repr(C)]
+struct XFixesSelectionNotifyEvent {
+    type_: c_int,
+    serial: c_ulong,
+    send_event: Bool,
+    display: *mut Display,
+    window: Window,
+    subtype: c_int,
+    owner: Window,
+    selection: Atom,
+    timestamp: Time,
+    selection_timestamp: Time,
+}
+
+/// Predicate for `XIfEvent` which waits for the reply to `XConvertSelection`
+/// while leaving the other events, such as selection changes, in the queue.
+unsafe extern "C" fn is_selection_notify(
+    _: *mut Display,
+    event: *mut XEvent,
+    _: *mut c_char,
+) -> c_int {
+    ((*event).get_type() == SelectionNotify) as c_int
+}
+
 impl ClipboardOwner {
     /// Creates a new instance of the clipboard.
     ///
@@ -66,6 +102,16 @@ impl ClipboardOwner {
             prop_id,
         })
     }
+
+    /// Gets the atom that identifies the selection.
+    fn selection_id(&self, selection: Selection) -> Result<Atom, Error> {
+        let name = match selection {
+            Selection::Clipboard => "CLIPBOARD",
+            Selection::Primary => "PRIMARY",
+        };
+        Ok(unsafe { XInternAtom(self.display, CString::new(name)?.as_ptr(), False) })
+    }
+
     /// Fetches the data stored in the clipboard according to the `target_id` which
     /// represents the target format the selection needs to be converted.
     fn get_clipboard(
@@ -86,13 +132,12 @@ impl ClipboardOwner {
                 CurrentTime,
             );
 
-            loop {
-                XNextEvent(self.display, event);
-
-                if event.type_ == SelectionNotify {
-                    break;
-                }
-            }
+            XIfEvent(
+                self.display,
+                event,
+                Some(is_selection_notify),
+                std::ptr::null_mut(),
+            );
 
             if event.selection.property == 0 {
                 bail!("The conversion could not be performed.");
@@ -157,18 +202,44 @@ impl ClipboardOwner {
             Ok(owner_title.to_string())
         }
     }
-}
 
-impl ClipboardFunctions for ClipboardOwner {
+    /// Gets the class of the selection owner from its `WM_CLASS` property, which
+    /// unlike the title stays the same for all windows of an application.
+    fn get_owner_class(&self, clipboard_id: Atom) -> Option<String> {
+        unsafe {
+            let owner = XGetSelectionOwner(self.display, clipboard_id);
+            let mut hint = XClassHint {
+                res_name: std::ptr::null_mut(),
+                res_class: std::ptr::null_mut(),
+            };
+            if owner == 0 || XGetClassHint(self.display, owner, &mut hint) == 0 {
+                return None;
+            }
+
+            let class = if hint.res_class.is_null() {
+                None
+            } else {
+                Some(
+                    CStr::from_ptr(hint.res_class)
+                        .to_string_lossy()
+                        .into_owned(),
+                )
+            };
+            for name in &[hint.res_name, hint.res_class] {
+                if !name.is_null() {
+                    XFree(*name as *mut _);
+                }
+            }
+            class
+        }
+    }
+
     /// Gets a hashmap of content type targets along with their atom identifier
-    /// that the clipboard owner can convert the data to. The current implementation
-    /// only handles HTML and text based formats i.e. text/html, UTF8_STRING, TEXT
-    fn get_targets(&self) -> Result<ClipboardTargets, Error> {
+    /// that the owner of the selection can convert the data to.
+    fn get_selection_targets(&self, clipboard_id: Atom) -> Result<ClipboardTargets, Error> {
         unsafe {
             let mut event: XEvent = mem::uninitialized();
             let targets_id = XInternAtom(self.display, CString::new("TARGETS")?.as_ptr(), False);
-            let clipboard_id =
-                XInternAtom(self.display, CString::new("CLIPBOARD")?.as_ptr(), False);
 
             // Listen to event when the selection is transferred
             XSelectInput(self.display, self.window, SelectionNotify.into());
@@ -182,13 +253,12 @@ impl ClipboardFunctions for ClipboardOwner {
                 CurrentTime,
             );
 
-            loop {
-                XNextEvent(self.display, &mut event);
-
-                if event.type_ == SelectionNotify || event.selection.selection == clipboard_id {
-                    break;
-                }
-            }
+            XIfEvent(
+                self.display,
+                &mut event,
+                Some(is_selection_notify),
+                std::ptr::null_mut(),
+            );
 
             if event.selection.property == 0 {
                 bail!("Could not convert selection to targets");
@@ -251,14 +321,15 @@ impl ClipboardFunctions for ClipboardOwner {
         }
     }
 
-    /// Fetches the selection stored in the clipboard if it can converted to a text-based format
+    /// Fetches the data stored in the selection if it can converted to a text-based format
     ///
     /// This method tries to convert the selection into a text-based format. If
     /// a non-text format like image copied to the clipboard and the selection
     /// owner is a browser then the owner might be able to convert into a HTML img
     /// tag with the source pointing to the URL of the image.
-    fn get_clipboard(&self) -> Result<ClipboardData, Error> {
-        let targets = match self.get_targets()? {
+    fn get_selection(&self, selection: Selection) -> Result<ClipboardData, Error> {
+        let clipboard_id = self.selection_id(selection)?;
+        let targets = match self.get_selection_targets(clipboard_id)? {
             ClipboardTargets::X11(x) => x,
             _ => unreachable!(),
         };
@@ -268,34 +339,174 @@ impl ClipboardFunctions for ClipboardOwner {
             .or_else(|| targets.get("UTF8_STRING"))
             .or_else(|| targets.get("TEXT"))
             .ok_or(format_err!("No text-based targets found."))?;
-        let clipboard_id =
-            unsafe { XInternAtom(self.display, CString::new("CLIPBOARD")?.as_ptr(), 0) };
         let mut event: XEvent = unsafe { mem::uninitialized() };
         let clipboard_data = self.get_clipboard(clipboard_id, *target_id, &mut event)?;
 
         // Add extra metadata such as the clipboard owner
         // and when the selection was copied from the owner
         let owner_title = self.get_owner_title(clipboard_id)?;
-        if targets.get("text/html").is_some() {
-            return Ok(ClipboardData::new((
-                clipboard_data,
-                Some(owner_title),
-                None,
-            )));
+        let mut data = if targets.contains_key("text/html") {
+            ClipboardData::new((clipboard_data, Some(owner_title), None))
+        } else {
+            ClipboardData::new((clipboard_data, Some(owner_title)))
+        };
+        data.meta_mut().selection = Some(selection);
+        data.meta_mut().owner_class = self.get_owner_class(clipboard_id);
+        Ok(data)
+    }
+
+    /// Takes over the clipboard and hands the text to the programs that paste
+    /// it until another program takes the clipboard over. Whether the
+    /// clipboard could be taken over is sent as soon as it is known.
+    fn serve_text(&self, text: &str, owned: mpsc::Sender<Result<(), Error>>) {
+        let result = (|| unsafe {
+            let clipboard_id = self.selection_id(Selection::Clipboard)?;
+            // Larger texts would have to be sent in chunks with INCR
+            if text.len() > XMaxRequestSize(self.display) as usize * 4 - 100 {
+                bail!("The text is too large to write to the clipboard");
+            }
+            XSetSelectionOwner(self.display, clipboard_id, self.window, CurrentTime);
+            if XGetSelectionOwner(self.display, clipboard_id) != self.window {
+                bail!("Could not take over the clipboard");
+            }
+            Ok(())
+        })();
+        let failed = result.is_err();
+        let _ = owned.send(result);
+        if failed {
+            return;
+        }
+
+        unsafe {
+            let atom =
+                |name: &str| XInternAtom(self.display, CString::new(name).unwrap().as_ptr(), False);
+            let utf8_string = atom("UTF8_STRING");
+            // TARGETS comes first
+            let targets = [atom("TARGETS"), utf8_string, atom("TEXT"), XA_STRING];
+            let mut event: XEvent = mem::zeroed();
+            loop {
+                XNextEvent(self.display, &mut event);
+                if event.get_type() == SelectionClear {
+                    break;
+                }
+                if event.get_type() == SelectionRequest {
+                    self.answer_request(&event.selection_request, text, &targets, utf8_string);
+                }
+            }
         }
+    }
 
-        Ok(ClipboardData::new((clipboard_data, Some(owner_title))))
+    /// Converts the text to the target that a program asked for, or refuses
+    /// the request when it is not one of the `targets`.
+    unsafe fn answer_request(
+        &self,
+        request: &XSelectionRequestEvent,
+        text: &str,
+        targets: &[Atom],
+        utf8_string: Atom,
+    ) {
+        // Clients older than the ICCCM leave the property unset
+        let property = if request.property == 0 {
+            request.target
+        } else {
+            request.property
+        };
+        let mut reply = XSelectionEvent {
+            type_: SelectionNotify,
+            serial: 0,
+            send_event: True,
+            display: self.display,
+            requestor: request.requestor,
+            selection: request.selection,
+            target: request.target,
+            property: 0,
+            time: request.time,
+        };
+        if request.target == targets[0] {
+            XChangeProperty(
+                self.display,
+                request.requestor,
+                property,
+                XA_ATOM,
+                32,
+                PropModeReplace,
+                targets.as_ptr() as *const c_uchar,
+                targets.len() as c_int,
+            );
+            reply.property = property;
+        } else if targets[1..].contains(&request.target) {
+            // TEXT leaves the type to the owner
+            let type_ = if request.target == XA_STRING {
+                XA_STRING
+            } else {
+                utf8_string
+            };
+            XChangeProperty(
+                self.display,
+                request.requestor,
+                property,
+                type_,
+                8,
+                PropModeReplace,
+                text.as_ptr(),
+                text.len() as c_int,
+            );
+            reply.property = property;
+        }
+        let mut reply = XEvent::from(reply);
+        XSendEvent(self.display, request.requestor, False, 0, &mut reply);
+        XFlush(self.display);
+    }
+}
+
+impl ClipboardFunctions for ClipboardOwner {
+    /// Gets a hashmap of content type targets along with their atom identifier
+    /// that the clipboard owner can convert the data to. The current implementation
+    /// only handles HTML and text based formats i.e. text/html, UTF8_STRING, TEXT
+    fn get_targets(&self) -> Result<ClipboardTargets, Error> {
+        self.get_selection_targets(self.selection_id(Selection::Clipboard)?)
     }
 
-    /// Watches the clipboard for changes and calls the callback function with
-    /// the clipboard data when the content changes. It depends on the XFixes
-    /// extension to request the XServer to notify the window whenever the selection
+    /// Fetches the selection stored in the clipboard if it can converted to a text-based format
+    fn get_clipboard(&self) -> Result<ClipboardData, Error> {
+        self.get_selection(Selection::Clipboard)
+    }
+
+    /// Writes the text like `xclip`: a thread with a connection and a window
+    /// of its own owns the clipboard and serves the text until something
+    /// else is copied. Only texts that fit in a single request are written.
+    fn writer(&self) -> Option<ClipboardWriter> {
+        Some(ClipboardWriter::new(|text| {
+            let text = text.to_string();
+            let (owned, result) = mpsc::channel();
+            thread::spawn(move || match ClipboardOwner::new() {
+                Ok(owner) => owner.serve_text(&text, owned),
+                Err(e) => {
+                    let _ = owned.send(Err(e));
+                }
+            });
+            result
+                .recv()
+                .map_err(|_| format_err!("Could not take over the clipboard"))?
+        }))
+    }
+
+    /// Watches the clipboard for changes with the default watch options.
+    fn watch_clipboard(&self, callback: &ClipboardSink) {
+        self.watch_selections(callback, &WatchOptions::default())
+    }
+
+    /// Watches the selections for changes and calls the callback function with
+    /// the selection data once it stops changing. It depends on the XFixes
+    /// extension to request the XServer to notify the window whenever a selection
     /// changes. It panics if it could not find the required extension.
+    ///
+    /// The data is not read on every notification. Instead the read is delayed
+    /// until the selection has been quiet for its debounce window, which is
+    /// checked by polling the connection to the XServer with a timeout.
     //  Based on the stackoverflow answer: https://stackoverflow.com/a/44992967
-    fn watch_clipboard(&self, callback: &ClipboardSink) {
+    fn watch_selections(&self, callback: &ClipboardSink, options: &WatchOptions) {
         unsafe {
-            let clipboard_id =
-                XInternAtom(self.display, CString::new("CLIPBOARD").unwrap().as_ptr(), 0);
             let mut event_base = mem::uninitialized();
             let mut error_base = mem::uninitialized();
             let mut event: XEvent = mem::uninitialized();
@@ -310,25 +521,53 @@ impl ClipboardFunctions for ClipboardOwner {
                 panic!("Could not use XFixes extenion");
             }
 
-            XFixesSelectSelectionInput(
-                self.display,
-                self.window,
-                clipboard_id,
-                XFixesSetSelectionOwnerNotifyMask as u64,
-            );
+            let mut selection_ids = HashMap::new();
+            for (selection, _) in options.windows() {
+                let selection_id = self.selection_id(selection).unwrap();
+                XFixesSelectSelectionInput(
+                    self.display,
+                    self.window,
+                    selection_id,
+                    XFixesSetSelectionOwnerNotifyMask as u64,
+                );
+                selection_ids.insert(selection_id, selection);
+            }
+            let mut debouncer = Debouncer::new(options.windows());
+            let connection = XConnectionNumber(self.display);
 
             loop {
-                XNextEvent(self.display, &mut event);
+                // Only block on the next event when no selection is waiting to settle
+                if XPending(self.display) == 0 {
+                    if let Some(timeout) = debouncer.timeout(Instant::now()) {
+                        let mut fd = libc::pollfd {
+                            fd: connection,
+                            events: libc::POLLIN,
+                            revents: 0,
+                        };
+                        libc::poll(&mut fd, 1, timeout.as_millis() as c_int + 1);
+                    }
+                }
+
+                while XPending(self.display) > 0 || debouncer.timeout(Instant::now()).is_none() {
+                    XNextEvent(self.display, &mut event);
 
-                if event.type_ == event_base + XFixesSelectionNotify {
-                    let clipboard_data = ClipboardFunctions::get_clipboard(self);
+                    if event.type_ == event_base + XFixesSelectionNotify {
+                        let notify =
+                            &*(&event as *const XEvent as *const XFixesSelectionNotifyEvent);
+                        if let Some(selection) = selection_ids.get(&notify.selection) {
+                            debouncer.touch(*selection, Instant::now());
+                        }
+                    }
+                }
 
-                    if clipboard_data.is_ok() {
-                        if let Err(e) = callback.0(clipboard_data.unwrap()) {
-                            eprintln!("An error has occured in the callback function {}", e);
+                for selection in debouncer.due(Instant::now()) {
+                    match self.get_selection(selection) {
+                        Ok(data) => {
+                            if let Err(e) = callback.0(data) {
+                                eprintln!("An error has occured in the callback function {}", e);
+                            }
                         }
-                    } else {
-                        eprintln!("{}", clipboard_data.unwrap_err());
+                        Err(e) => eprintln!("{}", e),
                     }
                 }
             }